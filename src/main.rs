@@ -1,14 +1,13 @@
-//todo: do not consider future turn when it's the last turn of the simulation
 //todo: add more comment to the complicated functions
-//todo: add a feature that tells you what turn do you run out of cards in averge 
 
 extern crate colored;
 extern crate fastrand;
 
-use std::sync::{Mutex, Arc};
 use std::thread;
+use std::sync::{Arc, RwLock};
+use std::time::Instant;
 use std::error::Error;
-use std::collections::HashSet;
+use std::collections::{HashSet, HashMap};
 use std::io::Read;
 use std::fs::File;
 use colored::*;
@@ -74,6 +73,366 @@ enum CardLocation
     InDeck,
     InHand,
     NoWhere,
+    Mulliganed,
+}
+
+///the max number of cards in hand. cards drew into a full hand are burned
+const HAND_LIMIT:usize = 10;
+
+///the max number of cards in a Dealer, one bit for each card slot
+const MAX_CARDS:usize = 64;
+
+///card slots kept for the cards the game gives: the coin and the starting card of the hero
+const RESERVED_SLOTS:usize = 2;
+
+///the max number of mana crystals
+const MAX_MANA:i8 = 10;
+
+///where every card is. one bit mask for each location, bit n is the card at position n in Dealer.cards
+///
+///copying it is cheap, so the search can try plays on copies
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default, Hash)]
+struct CardLocations
+{
+    deck:u64,
+    hand:u64,
+    ///CardLocation::NoWhere
+    played:u64,
+    ///CardLocation::Mulliganed
+    aside:u64,
+}
+
+impl CardLocations
+{
+    ///the first len cards in the deck
+    fn new_deck(len:usize) -> CardLocations
+    {
+        let deck = match len
+        {
+            MAX_CARDS=>u64::MAX,
+            _=>(1 << len) - 1,
+        };
+        CardLocations{deck, ..CardLocations::default()}
+    }
+
+    fn mask(&self, location:CardLocation) -> u64
+    {
+        match location
+        {
+            CardLocation::InDeck=>self.deck,
+            CardLocation::InHand=>self.hand,
+            CardLocation::NoWhere=>self.played,
+            CardLocation::Mulliganed=>self.aside,
+        }
+    }
+
+    fn mask_mut(&mut self, location:CardLocation) -> &mut u64
+    {
+        match location
+        {
+            CardLocation::InDeck=>&mut self.deck,
+            CardLocation::InHand=>&mut self.hand,
+            CardLocation::NoWhere=>&mut self.played,
+            CardLocation::Mulliganed=>&mut self.aside,
+        }
+    }
+
+    ///the location of the card at pos. a card slot not used is CardLocation::NoWhere
+    fn get(&self, pos:usize) -> CardLocation
+    {
+        let bit = 1 << pos;
+        [CardLocation::InDeck, CardLocation::InHand, CardLocation::Mulliganed].into_iter()
+            .find(|&lo| self.mask(lo) & bit != 0)
+            .unwrap_or(CardLocation::NoWhere)
+    }
+
+    fn set(&mut self, pos:usize, location:CardLocation)
+    {
+        self.free(pos);
+        *self.mask_mut(location) |= 1 << pos;
+    }
+
+    ///make the card slot at pos not used
+    fn free(&mut self, pos:usize)
+    {
+        let bit = !(1 << pos);
+        self.deck &= bit;
+        self.hand &= bit;
+        self.played &= bit;
+        self.aside &= bit;
+    }
+
+    ///number of cards in the location
+    fn count(&self, location:CardLocation) -> usize
+    {
+        self.mask(location).count_ones() as usize
+    }
+
+    ///positions of the cards in the location, from low to high
+    fn positions(&self, location:CardLocation) -> impl Iterator<Item = usize>
+    {
+        let mut mask = self.mask(location);
+        std::iter::from_fn(move ||
+        {
+            if mask == 0
+            {
+                return None;
+            }
+            let pos = mask.trailing_zeros() as usize;
+            mask &= mask - 1;
+            Some(pos)
+        })
+    }
+
+    ///position of the n-th card in the location. n must be less than count(location)
+    fn nth(&self, location:CardLocation, n:usize) -> usize
+    {
+        let mut mask = self.mask(location);
+        for _ in 0..n
+        {
+            mask &= mask - 1;
+        }
+        mask.trailing_zeros() as usize
+    }
+
+    ///move the card at last to pos, like Vec::swap_remove
+    fn swap_remove(&mut self, pos:usize, last:usize)
+    {
+        let location = self.get(last);
+        self.free(last);
+        if pos != last
+        {
+            self.set(pos, location);
+        }
+    }
+
+    ///remove the card at pos and move the cards after it one position down, like Vec::remove
+    fn remove(&mut self, pos:usize)
+    {
+        let low = (1u64 << pos) - 1;
+        for mask in [&mut self.deck, &mut self.hand, &mut self.played, &mut self.aside]
+        {
+            *mask = (*mask & low) | (mask.checked_shr(pos as u32 + 1).unwrap_or(0) << pos);
+        }
+    }
+}
+
+///the mana and the costs carried from one turn to the next. it's kept next to the CardLocations, and copied with them by the search
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+struct TurnState
+{
+    ///how much cheaper every card in hand got, by position in Dealer.cards. see Dealer::end_turn
+    discount:[i8; MAX_CARDS],
+    ///mana locked by the overload of the last turn
+    overload:i8,
+    ///the mana crystals at the end of the last turn minus its turn number: the ramp, the destroyed crystals and the cap. see crystals
+    extra_crystals:i16,
+}
+
+impl Default for TurnState
+{
+    fn default() -> TurnState
+    {
+        TurnState{discount:[0; MAX_CARDS], overload:0, extra_crystals:0}
+    }
+}
+
+impl TurnState
+{
+    ///the mana crystals of a turn: one more every turn and the extra_crystals, at most MAX_MANA
+    fn crystals(&self, turn:u8) -> i8
+    {
+        (turn as i16 + self.extra_crystals).clamp(0, MAX_MANA as i16) as i8
+    }
+
+    ///move the discount of the card at last to pos, like CardLocations::swap_remove
+    fn swap_remove(&mut self, pos:usize, last:usize)
+    {
+        self.discount[pos] = self.discount[last];
+        self.discount[last] = 0;
+    }
+
+    ///remove the discount of the card at pos, like CardLocations::remove
+    fn remove(&mut self, pos:usize)
+    {
+        self.discount.copy_within(pos+1.., pos);
+        self.discount[MAX_CARDS-1] = 0;
+    }
+}
+
+///what happened to a card draw
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum DrawResult
+{
+    ///the card is in hand now
+    InHand(usize),
+    ///the hand was full, the card is burned
+    Burned(usize),
+    ///the deck is empty
+    Fatigue,
+}
+
+///statistics of a simulation, averaged over the games.  
+///while simulating, every field holds the sum (or the count) instead, see SimStats::average
+#[derive(Debug, Clone, Default)]
+struct SimStats
+{
+    burned_cards:f64,
+    fatigue_damage:f64,
+    ///the turn the hand gets empty, in the games it does
+    hand_empty_turn:f64,
+    ///how often the hand gets empty before maxturn
+    hand_empty_rate:f64,
+    ///the turn the deck gets empty, in the games it does
+    deck_empty_turn:f64,
+    ///how often the deck gets empty before maxturn
+    deck_empty_rate:f64,
+    ///cards left in hand at the end of each turn
+    cards_in_hand:Vec<f64>,
+    ///how often all the mana is spent on cards in each turn
+    curve_out_rate:Vec<f64>,
+    ///mana spent on cards in each turn (the coin counts as 1)
+    mana_used:Vec<f64>,
+}
+
+impl SimStats
+{
+    fn new(maxturn:u8) -> SimStats
+    {
+        SimStats{
+                    cards_in_hand:vec![0.0; maxturn as usize],
+                    curve_out_rate:vec![0.0; maxturn as usize],
+                    mana_used:vec![0.0; maxturn as usize],
+                    ..SimStats::default()
+                }
+    }
+
+    ///add up the sums of another thread
+    fn merge(&mut self, other:&SimStats)
+    {
+        self.burned_cards += other.burned_cards;
+        self.fatigue_damage += other.fatigue_damage;
+        self.hand_empty_turn += other.hand_empty_turn;
+        self.hand_empty_rate += other.hand_empty_rate;
+        self.deck_empty_turn += other.deck_empty_turn;
+        self.deck_empty_rate += other.deck_empty_rate;
+        for (a,b) in self.cards_in_hand.iter_mut().zip(other.cards_in_hand.iter())
+        {
+            *a += b;
+        }
+        for (a,b) in self.curve_out_rate.iter_mut().zip(other.curve_out_rate.iter())
+        {
+            *a += b;
+        }
+        for (a,b) in self.mana_used.iter_mut().zip(other.mana_used.iter())
+        {
+            *a += b;
+        }
+    }
+
+    ///change the sums into averges of reps games
+    fn average(&mut self, reps:i32)
+    {
+        let reps = reps as f64;
+        self.burned_cards /= reps;
+        self.fatigue_damage /= reps;
+        if self.hand_empty_rate > 0.0
+        {
+            self.hand_empty_turn /= self.hand_empty_rate;
+        }
+        if self.deck_empty_rate > 0.0
+        {
+            self.deck_empty_turn /= self.deck_empty_rate;
+        }
+        self.hand_empty_rate /= reps;
+        self.deck_empty_rate /= reps;
+        self.cards_in_hand.iter_mut().for_each(|x| *x /= reps);
+        self.curve_out_rate.iter_mut().for_each(|x| *x /= reps);
+        self.mana_used.iter_mut().for_each(|x| *x /= reps);
+    }
+
+    fn print(&self)
+    {
+        match self.hand_empty_rate > 0.0
+        {
+            true=>println!("the hand gets empty in turn {} ({:.1}% of games)", format!("{:.2}", self.hand_empty_turn).yellow(), self.hand_empty_rate*100.0),
+            false=>println!("the hand never gets empty"),
+        }
+        match self.deck_empty_rate > 0.0
+        {
+            true=>println!("the deck gets empty in turn {} ({:.1}% of games)", format!("{:.2}", self.deck_empty_turn).yellow(), self.deck_empty_rate*100.0),
+            false=>println!("the deck never gets empty"),
+        }
+        println!("burned cards:{:.3}, fatigue damage:{:.3} per game", self.burned_cards, self.fatigue_damage);
+        println!("turn|cards in hand|curve out|mana used");
+        for (turn,((cards,curve),mana)) in self.cards_in_hand.iter().zip(self.curve_out_rate.iter()).zip(self.mana_used.iter()).enumerate()
+        {
+            println!("{:02}  |{:>13}|{:>8.1}%|{:>9.2}", turn+1, format!("{:.2}", cards), curve*100.0, mana);
+        }
+    }
+}
+
+///z value of a 95% confidence interval
+const Z_95:f64 = 1.96;
+
+///mean and standard error of the mean
+fn mean_and_std_error(scores:&[f64]) -> (f64, f64)
+{
+    let n = scores.len() as f64;
+    if scores.is_empty()
+    {
+        return (0.0, 0.0);
+    }
+    let mean = scores.iter().sum::<f64>() / n;
+    if scores.len() < 2
+    {
+        return (mean, 0.0);
+    }
+    let variance = scores.iter().map(|x| (x-mean)*(x-mean)).sum::<f64>() / (n-1.0);
+    (mean, (variance/n).sqrt())
+}
+
+///a keep pattern of a start hand and its simulated scores
+#[derive(Debug, Clone)]
+struct KeepPattern
+{
+    ///'1' for keep, '0' for toss, in the order of the sorted hand
+    pattern:String,
+    kept:Vec<usize>,
+    tossed:Vec<usize>,
+    ///the kept cards
+    cards:Vec<Card>,
+    ///score of every rep
+    scores:Vec<f64>,
+    stats:SimStats,
+}
+
+impl KeepPattern
+{
+    fn mean(&self) -> f64
+    {
+        mean_and_std_error(&self.scores).0
+    }
+
+    fn std_error(&self) -> f64
+    {
+        mean_and_std_error(&self.scores).1
+    }
+
+    ///95% confidence interval of the mean
+    fn confidence_interval(&self) -> (f64, f64)
+    {
+        let (mean, std_error) = mean_and_std_error(&self.scores);
+        (mean - Z_95*std_error, mean + Z_95*std_error)
+    }
+
+    ///true if the confidence intervals overlap, which means we can't tell which one is better
+    fn is_tied_with(&self, other:&KeepPattern) -> bool
+    {
+        let (low_a, high_a) = self.confidence_interval();
+        let (low_b, high_b) = other.confidence_interval();
+        low_a <= high_b && low_b <= high_a
+    }
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -84,46 +443,367 @@ enum CommandResult
     End,
 }
 
-#[derive(Debug, PartialEq, Eq, Clone)]
-enum Hero
+///how solve_mull spends the reps on the keep patterns
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum SimMode
+{
+    ///every pattern gets cycle_reps reps
+    Full,
+    ///simulate in rounds, drop the patterns that are clearly worse than the leader. the reps saved go to the contenders
+    Racing,
+}
+
+///number of rounds of SimMode::Racing
+const RACING_ROUNDS:i32 = 8;
+
+///number of random start hands keep_values tries for every card
+const KEEP_VALUE_HANDS:usize = 10;
+
+///optimize never puts more copies of a card than this into the deck
+const MAX_COPIES:usize = 2;
+
+///how play_a_turn guesses the score of the future turns
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Lookahead
+{
+    ///take the average of 10 random draws
+    Sample,
+    ///go through every different draw, weighted by its chance (expectimax)
+    Exact,
+}
+
+///how much every turn counts in the score. play_a_turn scales each turn, so the lookahead is weighted too
+#[derive(Debug, Clone, PartialEq)]
+enum TurnWeights
+{
+    Equal,
+    ///weights of turn 1, 2, 3... the turns after the list use the last weight
+    List(Vec<f64>),
+    ///turn n is weighted by factor^(n-1)
+    Discount(f64),
+}
+
+impl TurnWeights
+{
+    fn weight(&self, turn:u8) -> f64
+    {
+        match self
+        {
+            TurnWeights::Equal=>1.0,
+            TurnWeights::List(weights)=>*weights.get(turn.max(1) as usize - 1).or(weights.last()).unwrap_or(&1.0),
+            TurnWeights::Discount(factor)=>factor.powi(turn as i32 - 1),
+        }
+    }
+}
+
+impl fmt::Display for TurnWeights
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        match self
+        {
+            TurnWeights::Equal=>write!(f, "equal"),
+            TurnWeights::List(weights)=>write!(f, "{}", weights.iter().map(|w| w.to_string()).collect::<Vec<String>>().join(" ")),
+            TurnWeights::Discount(factor)=>write!(f, "x{} every turn", factor),
+        }
+    }
+}
+
+///key of the expectimax memo: (hand, deck, turn, mana crystals, locked mana, depth). hand and deck are sorted
+type MemoKey = (Vec<Card>, Vec<Card>, u8, i8, i8, u8);
+
+///key of the play pattern cache: (sorted hand, the coin included, mana_max, mana_min)
+type PlayKey = (Vec<Card>, i8, i8);
+
+///the play pattern cache is cleared when it gets this big
+const PLAY_CACHE_LIMIT:usize = 1 << 20;
+
+///how the replacement cards are drawn during the mulligan
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum MulliganRule
+{
+    ///tossed cards are kept out of the replacement draw, then shuffled back (the real game)
+    Hearthstone,
+    ///replacement cards are drawn from the full deck, tossed cards included
+    Redraw,
+}
+
+///the hero power. the search uses it when the mana left after the cards is enough
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct HeroPower
+{
+    cost:i8,
+    ///the score of one use
+    value:f64,
+    ///1 for the basic hero powers
+    uses_per_turn:u8,
+}
+
+impl HeroPower
 {
-    Warrior,
-    Priest,
-    Hunter,
-    Warlock,
-    Mage,
-    Rogue,
-    Shaman,
-    Paladin,
-    DemonHunter,
-    Druid,
+    ///how many times it can be used with the mana left
+    fn max_uses(&self, mana_left:i8) -> u8
+    {
+        if self.cost <= 0
+        {
+            return self.uses_per_turn;
+        }
+        ((mana_left.max(0) / self.cost) as u8).min(self.uses_per_turn)
+    }
+}
+
+///file of the hero definitions. the built-in heroes are used without it
+const HEROES_FILE:&str = "heroes.txt";
+
+///the built-in heroes, in the format of HEROES_FILE
+const DEFAULT_HEROES:&str = "warrior | wr | 2 0 | 7
+priest | pr | 2 0 | 813
+hunter | hu | 2 0 | 31
+warlock | wl | 2 0 | 893
+mage | ma | 2 0.5 | 637
+rogue | ro | 2 1.2 | 930
+shaman | sh | 2 0.8 | 1066
+paladin | pa | 2 1 | 671
+demonhunter | dh demon_hunter | 1 0.5 | 56550
+druid | dr | 2 0.5 | 274
+deathknight | dk death_knight | 2 0.5 | 78065";
+
+#[derive(Debug, PartialEq, Clone)]
+struct Hero
+{
+    name:String,
+    ///other names for the hero command
+    aliases:Vec<String>,
+    hero_power:HeroPower,
+    ///dbfId of the hero, used by deck codes
+    dbf_id:u32,
+    ///mana crystals the hero has before turn 1
+    starting_mana:i8,
+    ///a card put into the hand after the mulligan
+    starting_card:Option<Card>,
 }
 
 impl Hero
 {
-    fn hero_power_value(&self) -> f64
+    ///parse a line like `demonhunter | dh | 1 0.5 | 56550 | mana 1 card n0m1`: name, aliases, hero power cost, value and
+    ///uses per turn(optional), dbfId and the optional start of the game
+    fn parse(line:&str) -> Option<Hero>
+    {
+        let parts = line.split('|').map(|x| x.trim()).collect::<Vec<&str>>();
+        if parts.len() != 4 && parts.len() != 5
+        {
+            return None;
+        }
+        let power = parts[2].split_whitespace().collect::<Vec<&str>>();
+        if power.len() != 2 && power.len() != 3
+        {
+            return None;
+        }
+        let hero_power = HeroPower{
+                            cost:power[0].parse().ok()?,
+                            value:power[1].parse().ok()?,
+                            uses_per_turn:match power.get(2)
+                            {
+                                Some(n)=>n.parse().ok()?,
+                                None=>1,
+                            },
+                        };
+        let mut hero = Hero{
+                        name:parts[0].to_string(),
+                        aliases:parts[1].split_whitespace().map(|x| x.to_string()).collect(),
+                        hero_power,
+                        dbf_id:parts[3].parse().ok()?,
+                        starting_mana:0,
+                        starting_card:None,
+                    };
+        let mut start = parts.get(4).map(|x| x.split_whitespace()).into_iter().flatten();
+        while let Some(key) = start.next()
+        {
+            match key
+            {
+                "mana"=>hero.starting_mana = start.next()?.parse().ok()?,
+                "card"=>hero.starting_card = Some(Card::create(start.next()?)?),
+                _=>return None,
+            }
+        }
+        if hero.name.is_empty()
+        {
+            return None;
+        }
+        Some(hero)
+    }
+
+    fn defaults() -> Vec<Hero>
+    {
+        DEFAULT_HEROES.lines().map(|line| Hero::parse(line).unwrap()).collect()
+    }
+
+    ///the built-in heroes with the heroes of the file, one on each line. a hero with a built-in name replaces it.
+    ///empty lines and lines starting with # are skipped
+    fn load(filename:&str) -> Result<Vec<Hero>, String>
+    {
+        let contents = std::fs::read_to_string(filename).map_err(|_| "failed to read file".to_string())?;
+        let mut heroes = Hero::defaults();
+        for (i, line) in contents.lines().enumerate().filter(|(_,line)| !line.trim().is_empty() && !line.trim().starts_with('#'))
+        {
+            let hero = Hero::parse(line).ok_or(format!("failed to read hero at line {}", i+1))?;
+            match heroes.iter_mut().find(|x| x.name == hero.name)
+            {
+                Some(x)=>*x = hero,
+                None=>heroes.push(hero),
+            }
+        }
+        Ok(heroes)
+    }
+
+    fn is_called(&self, word:&str) -> bool
+    {
+        self.name == word || self.aliases.iter().any(|x| x == word)
+    }
+}
+
+///scores one turn for play_a_turn. the search adds the score of the future turns on top of it
+trait Evaluator: Send + Sync
+{
+    ///play:the cards played this turn. mana_left:the mana not spent on them or the hero power(locked mana not included).
+    ///hero:the hero, with the hero power in use. hero_power_uses:how many times the hero power is used. turn:the turn number
+    fn score_turn(&self, play:&[Card], mana_left:i8, hero:&Hero, hero_power_uses:u8, turn:u8) -> f64;
+}
+
+///the default score: 10 for every turn, -1 for every mana waste, the hero power value for every use, and a bonus for every card played.
+///a crystal locked by overload or spent on the hero power counts as a mana waste
+#[derive(Debug, Clone, Copy)]
+struct BaseEvaluator
+{
+    play_card_bonus:i8,
+}
+
+impl BaseEvaluator
+{
+    fn card_score(&self, card:&Card) -> f64
+    {
+        match card.card_power
+        {
+            CardPower::Strong=>0.5 + self.play_card_bonus as f64,
+            CardPower::Normal=>0.0 + self.play_card_bonus as f64,
+            CardPower::Weak=>-0.5 + self.play_card_bonus as f64,
+        }
+    }
+}
+
+impl Evaluator for BaseEvaluator
+{
+    fn score_turn(&self, play:&[Card], mana_left:i8, hero:&Hero, hero_power_uses:u8, _turn:u8) -> f64
+    {
+        let mut score = 10.0 - mana_left as f64 - play.iter().map(|x| x.overload() as f64).sum::<f64>();
+        score += hero_power_uses as f64 * (hero.hero_power.value - hero.hero_power.cost as f64);
+        score + play.iter().map(|card| self.card_score(card)).sum::<f64>()
+    }
+}
+
+///what a card does when it's played
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+enum CardEffect
+{
+    ///draw n cards
+    Draw(u8),
+    ///look at 3 random cards in the deck, put the best one into hand
+    Discover,
+    ///gain n mana this turn, like the coin
+    Mana(u8),
+    ///at the end of the turn, the cards in hand cost n less
+    Discount(u8),
+    ///costs n less at the end of every turn in hand
+    Cheaper(u8),
+    ///n mana of the next turn is locked
+    Overload(u8),
+    ///gain n empty mana crystals
+    EmptyCrystal(u8),
+    ///gain n full mana crystals, so they can be spent this turn
+    FullCrystal(u8),
+    ///destroy n of the mana crystals
+    DestroyCrystal(u8),
+}
+
+impl fmt::Display for CardEffect
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
     {
         match self
         {
-            Hero::Warrior => 0.0,
-            Hero::Priest => 0.0,
-            Hero::Hunter => 0.0,
-            Hero::Warlock => 0.0,
-            Hero::Mage => 0.5,
-            Hero::Rogue => 1.2,
-            Hero::Shaman => 0.8,
-            Hero::Paladin => 1.0,
-            Hero::DemonHunter => 0.5,  //per mana
-            Hero::Druid => 0.5,
+            CardEffect::Draw(n)=>write!(f, "d{}", n),
+            CardEffect::Discover=>write!(f, "v"),
+            CardEffect::Mana(n)=>write!(f, "m{}", n),
+            CardEffect::Discount(n)=>write!(f, "r{}", n),
+            CardEffect::Cheaper(n)=>write!(f, "c{}", n),
+            CardEffect::Overload(n)=>write!(f, "o{}", n),
+            CardEffect::EmptyCrystal(n)=>write!(f, "e{}", n),
+            CardEffect::FullCrystal(n)=>write!(f, "f{}", n),
+            CardEffect::DestroyCrystal(n)=>write!(f, "x{}", n),
         }
     }
 }
 
+impl CardEffect
+{
+    ///parse the effect part of a card string.
+    /// 
+    ///d<n> for draw n cards, v for discover, m<n> for gain n mana this turn,
+    ///r<n> for the cards in hand cost n less after this turn, c<n> for costs n less after every turn in hand,
+    ///o<n> for overload n, e<n> for gain n empty mana crystals, f<n> for gain n full mana crystals, x<n> for destroy n mana crystals
+    /// 
+    ///example: parse("d2v") returns [Draw(2), Discover]
+    fn parse(effects_str:&str) -> Option<Vec<CardEffect>>
+    {
+        let mut effects = Vec::new();
+        let mut chars = effects_str.chars().peekable();
+        while let Some(c) = chars.next()
+        {
+            match c
+            {
+                'd' | 'm' | 'r' | 'c' | 'o' | 'e' | 'f' | 'x'=>
+                {
+                    let mut num = String::new();
+                    while let Some(&n) = chars.peek()
+                    {
+                        if !n.is_ascii_digit()
+                        {
+                            break;
+                        }
+                        num.push(n);
+                        chars.next();
+                    }
+                    let n = match num.parse()
+                    {
+                        Ok(n)=>n,
+                        Err(_)=>return None,
+                    };
+                    effects.push(match c
+                    {
+                        'd'=>CardEffect::Draw(n),
+                        'm'=>CardEffect::Mana(n),
+                        'r'=>CardEffect::Discount(n),
+                        'c'=>CardEffect::Cheaper(n),
+                        'o'=>CardEffect::Overload(n),
+                        'e'=>CardEffect::EmptyCrystal(n),
+                        'f'=>CardEffect::FullCrystal(n),
+                        _=>CardEffect::DestroyCrystal(n),
+                    });
+                },
+                'v'=>effects.push(CardEffect::Discover),
+                _=>return None,
+            }
+        }
+        Some(effects)
+    }
+}
+
 #[derive(Clone, PartialEq, Eq, Hash)]
 struct Card
 {
     mana:i8,
-    card_power:CardPower
+    card_power:CardPower,
+    effects:Vec<CardEffect>,
 }
 
 impl fmt::Debug for Card {
@@ -137,10 +817,11 @@ impl fmt::Debug for Card {
             CardPower::Weak => result.red(),
         };
 
+        let effects = self.effects.iter().map(|x| x.to_string()).collect::<String>();
         let result = match self.mana 
         {
             -1=>format!("{}", "coin".yellow()),
-            _=>format!("{}", result),
+            _=>format!("{}{}", result, effects.cyan()),
         };
         f.pad(&result)
      }
@@ -149,44 +830,119 @@ impl fmt::Debug for Card {
 
 impl Card
 {
+    ///create a card with no effect
+    fn new(mana:i8, card_power:CardPower) -> Card
+    {
+        Card{mana, card_power, effects:Vec::new()}
+    }
+
     ///create a card with string.  
     /// 
     ///s for strong, n for normal, w for weak  
     /// 
     ///example create("n2"); return 2mana normal card  
     /// 
-    ///if the letter is not given , assume it's normal
+    ///if the letter is not given , assume it's normal. it can be put after the mana too, like create("2s")
+    /// 
+    ///effects follow the mana cost. d<n> for draw n cards, v for discover
+    /// 
+    ///example create("n1d2"); return 1mana normal card that draws 2 cards
     fn create(card_str:&str) -> Option<Card>
     {
         let power;
         let mana;
-        match card_str.chars().nth(0)
+        let body = match card_str.chars().nth(0)
         {
-            Some('s')=>power=CardPower::Strong,
-            Some('n')=>power=CardPower::Normal,
-            Some('w')=>power=CardPower::Weak,
-            Some(num)=>
+            Some('s')=>{power=CardPower::Strong; &card_str[1..]},
+            Some('n')=>{power=CardPower::Normal; &card_str[1..]},
+            Some('w')=>{power=CardPower::Weak; &card_str[1..]},
+            Some(num) if num.is_ascii_digit()=>
             {
-                if num >= '0' && num <= '9'
-                {
-                    power=CardPower::Normal;
-                }
-                else
+                let digits = card_str.find(|x:char| !x.is_ascii_digit()).unwrap_or(card_str.len());
+                if let Some(letter @ ('s' | 'n' | 'w')) = card_str[digits..].chars().next()
                 {
-                    return None;
+                    return Card::create(&format!("{}{}{}", letter, &card_str[..digits], &card_str[digits+1..]));
                 }
+                power=CardPower::Normal;
+                card_str
             },
-            None=>return None,
+            _=>return None,
         };
-        match card_str.chars().filter(|&x| x>='0' && x<='9').collect::<String>().parse()
+        let effects_pos = body.find(|x:char| !x.is_ascii_digit()).unwrap_or(body.len());
+        match body[..effects_pos].parse()
         {
             Ok(n)=>mana=n,
             Err(_)=>return None,
         }
-        let card = Card{mana:mana, card_power:power};
+        let effects = CardEffect::parse(&body[effects_pos..])?;
+        let card = Card{mana, card_power:power, effects};
         Some(card)
     }
 
+    ///mana the card gives for this turn when played
+    fn temp_mana(&self) -> i8
+    {
+        self.effects.iter().map(|x| match x
+        {
+            CardEffect::Mana(n) | CardEffect::FullCrystal(n)=>*n as i8,
+            _=>0,
+        }).sum()
+    }
+
+    ///the mana really spent on the card: the cost minus the mana it gives. the coin is -1
+    fn net_cost(&self) -> i8
+    {
+        self.mana - self.temp_mana()
+    }
+
+    ///how much cheaper the cards in hand get after the turn the card is played
+    fn discount(&self) -> i8
+    {
+        self.effects.iter().map(|x| match x
+        {
+            CardEffect::Discount(n)=>*n as i8,
+            _=>0,
+        }).sum()
+    }
+
+    ///mana crystals the card adds, or removes if it's less than 0
+    fn crystals_gained(&self) -> i8
+    {
+        self.effects.iter().map(|x| match x
+        {
+            CardEffect::EmptyCrystal(n) | CardEffect::FullCrystal(n)=>*n as i8,
+            CardEffect::DestroyCrystal(n)=>-(*n as i8),
+            _=>0,
+        }).sum()
+    }
+
+    ///mana of the next turn locked by the card
+    fn overload(&self) -> i8
+    {
+        self.effects.iter().map(|x| match x
+        {
+            CardEffect::Overload(n)=>*n as i8,
+            _=>0,
+        }).sum()
+    }
+
+    ///how much cheaper the card gets after every turn in hand
+    fn cheaper_each_turn(&self) -> i8
+    {
+        self.effects.iter().map(|x| match x
+        {
+            CardEffect::Cheaper(n)=>*n as i8,
+            _=>0,
+        }).sum()
+    }
+
+    ///the card string used by save and create
+    fn to_card_string(&self) -> String
+    {
+        let effects = self.effects.iter().map(|x| x.to_string()).collect::<String>();
+        format!("{}{}{}", self.card_power.to_char(), self.mana, effects)
+    }
+
     ///create cards with same stats
     /// 
     ///example: 3s4 means create 3 strong 4mana-cost cards
@@ -225,7 +981,7 @@ impl Ord for Card
         {
             Ordering::Equal=>
             {
-                self.card_power.cmp(&other.card_power) 
+                self.card_power.cmp(&other.card_power).then_with(|| self.effects.cmp(&other.effects))
             },
             other_order => other_order,
         }
@@ -246,7 +1002,13 @@ impl PartialOrd for Card
 struct Dealer
 {
     cards:Vec<Card>,
-    card_location:Vec<CardLocation>, 
+    card_location:CardLocations,
+    ///the mana and the costs of the game in card_location
+    turn_state:TurnState,
+    ///every random draw goes through it
+    rng:fastrand::Rng,
+    ///position of the starting card of the hero in cards, if it's added. see Simulator::set_start_hand
+    start_card:Option<usize>,
 }
 
 impl fmt::Debug for Dealer
@@ -256,7 +1018,7 @@ impl fmt::Debug for Dealer
         let mut result = String::new();
         for card in self.cards.iter()
         {
-            result += format!("mana:{} , power:{:?}\n", card.mana, card.card_power).as_str();
+            result += format!("mana:{} , power:{:?} , effects:{:?}\n", card.mana, card.card_power, card.effects).as_str();
         }
         f.pad(&result)
     }
@@ -267,7 +1029,7 @@ impl Dealer
     ///save the deck
     fn save(&self, filename:String)
     {
-        let save_data = self.cards.iter().map(|x| format!("{}{}",x.card_power.to_char(), x.mana)).collect::<Vec<String>>().join(" ");
+        let save_data = self.cards.iter().map(|x| x.to_card_string()).collect::<Vec<String>>().join(" ");
         std::fs::write(filename, save_data).expect("failed to write file");
     }
 
@@ -288,19 +1050,99 @@ impl Dealer
         self.clear();
         for card in contents.trim().split(' ')
         {
-            match Card::create(card)
+            let result = match Card::create(card)
             {
                 Some(c)=>self.insert_card(c),
                 None=>return CommandResult::Err("failed to create card".to_string()),
             };
+            if let CommandResult::Err(_) = result
+            {
+                return result;
+            }
+        }
+        CommandResult::Ok
+    }
+
+    ///load deck from a deck code of the game. return the hero dbfId
+    fn load_code(&mut self, code:&str, db:&CardDb) -> Result<Option<u32>, String>
+    {
+        let (heroes, cards) = decode_deckstring(code).ok_or("it's not a deck code".to_string())?;
+        let missing = cards.iter().filter(|(id,_)| !db.cards.contains_key(id)).map(|(id,_)| id.to_string()).collect::<Vec<String>>();
+        if !missing.is_empty()
+        {
+            return Err(format!("cards not in the card database: {}", missing.join(" ")));
+        }
+        self.clear();
+        for (id, copies) in cards.iter()
+        {
+            for _ in 0..*copies
+            {
+                if let CommandResult::Err(e) = self.insert_card(db.cards[id].clone())
+                {
+                    return Err(e);
+                }
+            }
+        }
+        self.sort_deck();
+        Ok(heroes.first().copied())
+    }
+
+    ///the deck code of the game for the deck. every card gets a card of the database with the same mana and power,
+    ///2 copies at most for each dbfId while there are enough of them. effects are not kept
+    fn to_code(&self, hero:u32, db:&CardDb) -> Result<String, String>
+    {
+        let mut kinds:Vec<(Card, u32)> = Vec::new();
+        for card in self.cards.iter().filter(|x| x.mana != -1)
+        {
+            let card = Card::new(card.mana, card.card_power.clone());
+            match kinds.iter_mut().find(|(c,_)| c == &card)
+            {
+                Some((_,count))=>*count += 1,
+                None=>kinds.push((card, 1)),
+            }
+        }
+        let mut result = Vec::new();
+        for (card, count) in kinds.into_iter()
+        {
+            let ids = db.ids_of(&card);
+            if ids.is_empty()
+            {
+                return Err(format!("no card like {} in the card database", card.to_card_string()));
+            }
+            let mut left = count;
+            for (i, id) in ids.iter().enumerate()
+            {
+                let copies = match i == ids.len()-1
+                {
+                    true=>left,
+                    false=>left.min(2),
+                };
+                result.push((*id, copies));
+                left -= copies;
+                if left == 0
+                {
+                    break;
+                }
+            }
         }
-        CommandResult::Ok
+        Ok(encode_deckstring(hero, &result))
     }
 
-    fn insert_card(&mut self, card:Card)
+    ///put a card into the deck. there are only MAX_CARDS card slots, and only the coin can take the RESERVED_SLOTS
+    fn insert_card(&mut self, card:Card) -> CommandResult
     {
+        let limit = match card.mana
+        {
+            -1=>MAX_CARDS,
+            _=>MAX_CARDS - RESERVED_SLOTS,
+        };
+        if self.cards.len() >= limit
+        {
+            return CommandResult::Err("too much cards!".to_string());
+        }
+        self.card_location.set(self.cards.len(), CardLocation::InDeck);
         self.cards.push(card);
-        self.card_location.push(CardLocation::InDeck);
+        CommandResult::Ok
     }
 
     ///fill the deck with high cost cards
@@ -319,19 +1161,21 @@ impl Dealer
     fn clear(&mut self)
     {
         self.cards.clear();
-        self.card_location = Vec::new();
+        self.card_location = CardLocations::default();
+        self.turn_state = TurnState::default();
+        self.start_card = None;
     }
 
-    ///reset card location back to the deck. delete the coin if there is one.
+    ///reset card location back to the deck. delete the coin and the starting card if there are.
     fn reset_deck(&mut self)
     {
-        self.card_location.fill(CardLocation::InDeck);
-        self.cards = self.cards.clone().into_iter().filter(|x| x.mana!=-1).collect();
-        let num_need_pop =  self.card_location.len() - self.cards.len();
-        for _ in 0..num_need_pop
+        if let Some(pos) = self.start_card.take()
         {
-            self.card_location.pop();
+            self.cards.remove(pos);
         }
+        self.cards.retain(|x| x.mana!=-1);
+        self.card_location = CardLocations::new_deck(self.cards.len());
+        self.turn_state = TurnState::default();
     }
 
     ///insert cards
@@ -351,55 +1195,239 @@ impl Dealer
         };
         for card in cards.into_iter()
         {
-            self.insert_card(card);
+            if let CommandResult::Err(e) = self.insert_card(card)
+            {
+                return CommandResult::Err(e);
+            }
         }
         CommandResult::Ok
     }
 
     ///draw a card randomly. return the position of the card drew.
-    fn draw_card(&mut self, card_locations:Option<&mut Vec<CardLocation>>) -> usize
+    /// 
+    ///if the hand is full the card is burned. if the deck is empty, return DrawResult::Fatigue
+    fn draw_card(&mut self, card_locations:Option<&mut CardLocations>) -> DrawResult
     {
         let locations = match card_locations
         {
             Some(x)=>x,
             None=>&mut self.card_location,
         };
-        let len = locations.iter().filter(|x| x == &&CardLocation::InDeck).count();
-        //eprintln!("the len is {}", len);
-        let pos = fastrand::usize(..len);
-        let (pos_in_deck,location) = locations.iter_mut().enumerate().filter(|(_,lo)| lo == &&CardLocation::InDeck).nth(pos).unwrap();
-        *location = CardLocation::InHand;
-        pos_in_deck
+        let len = locations.count(CardLocation::InDeck);
+        if len == 0
+        {
+            return DrawResult::Fatigue;
+        }
+        let pos_in_deck = locations.nth(CardLocation::InDeck, self.rng.usize(..len));
+        if locations.count(CardLocation::InHand) >= HAND_LIMIT
+        {
+            locations.set(pos_in_deck, CardLocation::NoWhere);
+            return DrawResult::Burned(pos_in_deck);
+        }
+        locations.set(pos_in_deck, CardLocation::InHand);
+        DrawResult::InHand(pos_in_deck)
+    }
+
+    ///discover a card from the deck: look at 3 random cards, put the best one into hand and leave the others.  
+    ///stronger cards are better, then cheaper ones. the card is burned if the hand is full.
+    /// 
+    ///the deck must not be empty
+    fn discover_card(&mut self, card_locations:Option<&mut CardLocations>) -> DrawResult
+    {
+        let locations = match card_locations
+        {
+            Some(x)=>x,
+            None=>&mut self.card_location,
+        };
+        let mut in_deck = locations.positions(CardLocation::InDeck).collect::<Vec<usize>>();
+        let mut options = Vec::new();
+        for _ in 0..3
+        {
+            if in_deck.is_empty()
+            {
+                break;
+            }
+            options.push(in_deck.swap_remove(self.rng.usize(..in_deck.len())));
+        }
+        let cards = &self.cards;
+        let pos = options.into_iter()
+                    .max_by(|&a,&b| cards[a].card_power.cmp(&cards[b].card_power).then(cards[b].mana.cmp(&cards[a].mana)))
+                    .unwrap();
+        if locations.count(CardLocation::InHand) >= HAND_LIMIT
+        {
+            locations.set(pos, CardLocation::NoWhere);
+            return DrawResult::Burned(pos);
+        }
+        locations.set(pos, CardLocation::InHand);
+        DrawResult::InHand(pos)
+    }
+
+    ///every different card that can be drawn, with its chance and the card locations after the draw.  
+    ///a card drawn into a full hand is burned, like draw_card. if the deck is empty, nothing changes
+    fn draw_outcomes(&self, card_locations:CardLocations) -> Vec<(f64, CardLocations)>
+    {
+        let deck_size = card_locations.count(CardLocation::InDeck);
+        if deck_size == 0
+        {
+            return vec![(1.0, card_locations)];
+        }
+        let hand_full = card_locations.count(CardLocation::InHand) >= HAND_LIMIT;
+
+        //(first position of the card, number of copies)
+        let mut kinds:Vec<(usize, usize)> = Vec::new();
+        for pos in card_locations.positions(CardLocation::InDeck)
+        {
+            match kinds.iter_mut().find(|(p,_)| self.cards[*p] == self.cards[pos])
+            {
+                Some((_,count))=>*count += 1,
+                None=>kinds.push((pos, 1)),
+            }
+        }
+
+        kinds.into_iter().map(|(pos,count)|
+        {
+            let mut locations = card_locations;
+            locations.set(pos, match hand_full
+            {
+                true=>CardLocation::NoWhere,
+                false=>CardLocation::InHand,
+            });
+            (count as f64 / deck_size as f64, locations)
+        }).collect()
+    }
+
+    ///count the cards left in the deck
+    fn deck_size(&self, card_locations:Option<&CardLocations>) -> usize
+    {
+        let locations = match card_locations
+        {
+            Some(x)=>x,
+            None=>&self.card_location,
+        };
+        locations.count(CardLocation::InDeck)
+    }
+
+    ///every different start hand of size cards (the coin not included), with its chance. the cards of a hand are sorted
+    fn hand_types(&self, size:usize) -> Vec<(Vec<Card>, f64)>
+    {
+        //(card, number of copies)
+        let mut kinds:Vec<(Card, usize)> = Vec::new();
+        for card in self.cards.iter().filter(|x| x.mana != -1)
+        {
+            match kinds.iter_mut().find(|(c,_)| c == card)
+            {
+                Some((_,count))=>*count += 1,
+                None=>kinds.push((card.clone(), 1)),
+            }
+        }
+        kinds.sort();
+
+        fn choose(n:usize, k:usize) -> f64
+        {
+            if n < k
+            {
+                return 0.0;
+            }
+            (0..k).map(|i| (n - i) as f64 / (k - i) as f64).product()
+        }
+
+        //(hand, number of ways to draw it)
+        let mut hands:Vec<(Vec<Card>, f64)> = vec![(Vec::new(), 1.0)];
+        for (card, count) in kinds.iter()
+        {
+            let mut next = Vec::new();
+            for (hand, ways) in hands.into_iter()
+            {
+                for copies in 0..=(*count).min(size - hand.len())
+                {
+                    let mut new_hand = hand.clone();
+                    new_hand.extend(std::iter::repeat_n(card.clone(), copies));
+                    next.push((new_hand, ways * choose(*count, copies)));
+                }
+            }
+            hands = next;
+        }
+
+        let deck_size = kinds.iter().map(|(_,count)| count).sum::<usize>();
+        let total = choose(deck_size, size);
+        let mut result = hands.into_iter().filter(|(hand,_)| hand.len() == size).map(|(hand, ways)| (hand, ways / total)).collect::<Vec<(Vec<Card>, f64)>>();
+        result.sort_by(|a,b| a.0.cmp(&b.0));
+        result
+    }
+
+    ///put a card the game gives (the coin, the starting card of the hero) into hand. it can take the RESERVED_SLOTS.
+    ///return its position, or None if there is no card slot left
+    fn give_card(&mut self, card:Card) -> Option<usize>
+    {
+        if self.cards.len() >= MAX_CARDS
+        {
+            return None;
+        }
+        self.cards.push(card);
+        let pos = self.cards.len()-1;
+        self.card_location.set(pos, CardLocation::InHand);
+        Some(pos)
+    }
+
+    ///put the tossed cards aside, so the replacement draw can't pick them again
+    fn set_aside(&mut self, tossed:&[usize]) -> bool
+    {
+        //check every card first, so nothing is left aside if one of them can't be
+        if tossed.iter().any(|&p| p >= self.cards.len() || self.card_location.get(p) != CardLocation::InDeck)
+        {
+            return false;
+        }
+        for &card_pos in tossed.iter()
+        {
+            self.card_location.set(card_pos, CardLocation::Mulliganed);
+        }
+        true
+    }
+
+    ///shuffle the cards put aside by set_aside back into the deck
+    fn shuffle_back(&mut self)
+    {
+        self.card_location.deck |= self.card_location.aside;
+        self.card_location.aside = 0;
     }
 
     fn new() -> Dealer
     {
-        Dealer{cards:Vec::new(), card_location:Vec::new()}
+        Dealer{cards:Vec::new(), card_location:CardLocations::default(), turn_state:TurnState::default(), rng:fastrand::Rng::new(), start_card:None}
     }
 
     ///change a card from deck to hand
     fn deck_to_hand(&mut self, card_pos:usize) -> bool
     {
-        match self.card_location.get_mut(card_pos)
+        if card_pos >= self.cards.len()
         {
-            Some(lo)=>*lo=CardLocation::InHand,
-            None=>return false,
+            return false;
         }
-        return true;
+        self.card_location.set(card_pos, CardLocation::InHand);
+        true
     }
 
     ///get the position of a card
     ///location:the location of the searching card.(example: CardLocation::InDeck)
     ///card_locations:locations of all cards. if it's None, use self.card_location
-    fn get_card_pos(&self, card:Card, location:&CardLocation, card_locations:Option<&Vec<CardLocation>>) -> Option<usize>
+    ///turn_state:the cost of the cards in hand. if it's None, use self.turn_state
+    fn get_card_pos(&self, card:Card, location:&CardLocation, card_locations:Option<&CardLocations>, turn_state:Option<&TurnState>) -> Option<usize>
     {
         let card_locations = match card_locations
         {
-            Some(x)=>x.to_vec(),
-            None=>self.card_location.clone(),
+            Some(x)=>x,
+            None=>&self.card_location,
+        };
+        let turn_state = match turn_state
+        {
+            Some(x)=>x,
+            None=>&self.turn_state,
         };
-        self.cards.iter().zip(card_locations.iter())
-                .position(|(c,lo)| c.mana == card.mana && c.card_power == card.card_power && lo == location)
+        card_locations.positions(*location).find(|&p|
+        {
+            let c = &self.cards[p];
+            c.card_power == card.card_power && c.effects == card.effects && self.cost_at(p, turn_state) == card.mana
+        })
     }
 
     ///change 「position in deck」 vector to 「struct Card」 vector
@@ -409,29 +1437,70 @@ impl Dealer
     }
 
 
-    fn get_hand(&self, card_locations:&Vec<CardLocation>) -> Vec<Card>
+    ///the cards in hand, with the cost they have now
+    fn get_hand(&self, card_locations:&CardLocations, turn_state:&TurnState) -> Vec<Card>
+    {
+        card_locations.positions(CardLocation::InHand).map(|p| self.card_at(p, turn_state)).collect()
+    }
+
+    ///the card at pos with the cost it has now: the discount is taken off, but never below 0
+    fn card_at(&self, pos:usize, turn_state:&TurnState) -> Card
+    {
+        Card{mana:self.cost_at(pos, turn_state), ..self.cards[pos].clone()}
+    }
+
+    fn cost_at(&self, pos:usize, turn_state:&TurnState) -> i8
+    {
+        match self.cards[pos].mana
+        {
+            mana if mana > 0=>mana.saturating_sub(turn_state.discount[pos]).max(0),
+            mana=>mana,
+        }
+    }
+
+    ///the cards left in hand get cheaper at the end of a turn: by the discount of the cards played, and by their own cheaper_each_turn.
+    ///the overload of the cards played locks the mana of the next turn, and the mana crystals they add or destroy are kept
+    ///
+    ///card_locations and turn_state are both given, or both None for self.card_location and self.turn_state
+    fn end_turn(&mut self, play:&[Card], turn:u8, card_locations:Option<&CardLocations>, turn_state:Option<&mut TurnState>)
     {
-        self.cards.clone().into_iter().zip(card_locations.iter())
-                .filter(|(_,lo)| lo == &&CardLocation::InHand).map(|(c,_)| c).collect()
+        let locations = match card_locations
+        {
+            Some(x)=>*x,
+            None=>self.card_location,
+        };
+        let state = match turn_state
+        {
+            Some(x)=>x,
+            None=>&mut self.turn_state,
+        };
+        state.overload = play.iter().map(|x| x.overload()).sum();
+        let crystals = (state.crystals(turn) + play.iter().map(|x| x.crystals_gained()).sum::<i8>()).clamp(0, MAX_MANA);
+        state.extra_crystals = crystals as i16 - turn as i16;
+        let discount = play.iter().map(|x| x.discount()).sum::<i8>();
+        for pos in locations.positions(CardLocation::InHand)
+        {
+            let cheaper = discount.saturating_add(self.cards[pos].cheaper_each_turn());
+            state.discount[pos] = state.discount[pos].saturating_add(cheaper);
+        }
     }
 
     ///change 「struct Card」 vector to 「position in deck」 vector
     ///can't work with CardLocation::NoWhere
-    fn cards_to_position(&mut self, cards:Vec<Card>, locations:CardLocation, card_locations:Option<&Vec<CardLocation>>) -> Option<Vec<usize>>
+    fn cards_to_position(&self, cards:Vec<Card>, locations:CardLocation, card_locations:Option<&CardLocations>) -> Option<Vec<usize>>
     {
         let mut pos_set = Vec::new();
-        let locations_temp = self.card_location.clone();
+        let mut locations_temp = match card_locations
+        {
+            Some(x)=>*x,
+            None=>self.card_location,
+        };
         for card in cards.into_iter()
         {
-            let pos = match self.get_card_pos(card.clone(), &locations, card_locations)
-            {
-                Some(p)=>p,
-                None=>return None,
-            };
+            let pos = self.get_card_pos(card, &locations, Some(&locations_temp), None)?;
             pos_set.push(pos);
-            self.card_location[pos] = CardLocation::NoWhere;
+            locations_temp.set(pos, CardLocation::NoWhere);
         }
-        self.card_location = locations_temp;
         Some(pos_set)
     }
 
@@ -446,12 +1515,12 @@ impl Dealer
             {
                 let coin_pos = self.cards.iter().position(|x| x.mana==-1).unwrap();
                 self.cards.swap_remove(coin_pos);
-                self.card_location.swap_remove(coin_pos);
+                self.card_location.swap_remove(coin_pos, self.cards.len());
+                self.turn_state.swap_remove(coin_pos, self.cards.len());
             },
             (PlayOrder::Second,false)=>
             {
-                self.cards.push(Card{mana:-1, card_power:CardPower::Normal});
-                self.card_location.push(CardLocation::InDeck);
+                self.insert_card(Card::new(-1, CardPower::Normal));
             },
             _=>(),
         }
@@ -465,6 +1534,176 @@ impl Dealer
 }
 
 
+///the card database used by deck codes
+const CARD_DB_FILE:&str = "cards.json";
+
+///cards of the game by dbfId, read from a json array like the cards.json of HearthstoneJSON.
+///every entry needs "dbfId" and "cost". "power" (s, n or w) is optional, normal by default
+struct CardDb
+{
+    cards:HashMap<u32, Card>,
+}
+
+impl CardDb
+{
+    fn load(filename:&str) -> Result<CardDb, String>
+    {
+        let contents = std::fs::read_to_string(filename).map_err(|_| format!("failed to read {}", filename))?;
+        CardDb::from_json(&contents)
+    }
+
+    fn from_json(contents:&str) -> Result<CardDb, String>
+    {
+        let json:serde_json::Value = serde_json::from_str(contents).map_err(|e| format!("failed to read the card database: {}", e))?;
+        let entries = json.as_array().ok_or("the card database should be a json array".to_string())?;
+        let mut cards = HashMap::new();
+        for entry in entries.iter()
+        {
+            let (dbf_id, cost) = match (entry["dbfId"].as_u64(), entry["cost"].as_i64())
+            {
+                (Some(id), Some(cost))=>(id as u32, cost as i8),
+                _=>continue,
+            };
+            let card_power = match entry["power"].as_str()
+            {
+                None | Some("n")=>CardPower::Normal,
+                Some("s")=>CardPower::Strong,
+                Some("w")=>CardPower::Weak,
+                Some(p)=>return Err(format!("unknown power {} of card {}", p, dbf_id)),
+            };
+            cards.insert(dbf_id, Card::new(cost, card_power));
+        }
+        Ok(CardDb{cards})
+    }
+
+    ///dbfIds of the cards with the same mana and power as card, from the lowest
+    fn ids_of(&self, card:&Card) -> Vec<u32>
+    {
+        let mut ids = self.cards.iter().filter(|(_,c)| c.mana == card.mana && c.card_power == card.card_power).map(|(&id,_)| id).collect::<Vec<u32>>();
+        ids.sort();
+        ids
+    }
+}
+
+const BASE64_CHARS:&[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64_encode(bytes:&[u8]) -> String
+{
+    let mut result = String::new();
+    for chunk in bytes.chunks(3)
+    {
+        let n = chunk.iter().enumerate().fold(0u32, |n,(i,&b)| n | (b as u32) << (16 - 8*i));
+        for i in 0..4
+        {
+            match i <= chunk.len()
+            {
+                true=>result.push(BASE64_CHARS[(n >> (18 - 6*i)) as usize & 63] as char),
+                false=>result.push('='),
+            }
+        }
+    }
+    result
+}
+
+fn base64_decode(text:&str) -> Option<Vec<u8>>
+{
+    let mut result = Vec::new();
+    let mut n = 0u32;
+    let mut bits = 0;
+    for c in text.trim().trim_end_matches('=').bytes()
+    {
+        n = n << 6 | BASE64_CHARS.iter().position(|&x| x == c)? as u32;
+        bits += 6;
+        if bits >= 8
+        {
+            bits -= 8;
+            result.push((n >> bits) as u8);
+        }
+    }
+    Some(result)
+}
+
+fn write_varint(bytes:&mut Vec<u8>, mut n:u32)
+{
+    while n >= 0x80
+    {
+        bytes.push((n as u8 & 0x7f) | 0x80);
+        n >>= 7;
+    }
+    bytes.push(n as u8);
+}
+
+fn read_varint(bytes:&mut impl Iterator<Item = u8>) -> Option<u32>
+{
+    let mut n = 0u32;
+    for shift in (0..35).step_by(7)
+    {
+        let b = bytes.next()?;
+        n |= ((b & 0x7f) as u32) << shift;
+        if b & 0x80 == 0
+        {
+            return Some(n);
+        }
+    }
+    None
+}
+
+///(dbfId, copies) of every card of a deck code
+type DeckList = Vec<(u32, u32)>;
+
+///decode a deck code of the game. return the hero dbfIds and the cards
+fn decode_deckstring(code:&str) -> Option<(Vec<u32>, DeckList)>
+{
+    let bytes = base64_decode(code)?;
+    let mut bytes = bytes.into_iter();
+    if bytes.next()? != 0 || read_varint(&mut bytes)? != 1
+    {
+        return None;
+    }
+    let _format = read_varint(&mut bytes)?;
+    let heroes = (0..read_varint(&mut bytes)?).map(|_| read_varint(&mut bytes)).collect::<Option<Vec<u32>>>()?;
+    let mut cards = Vec::new();
+    for copies in [1, 2]
+    {
+        for _ in 0..read_varint(&mut bytes)?
+        {
+            cards.push((read_varint(&mut bytes)?, copies));
+        }
+    }
+    for _ in 0..read_varint(&mut bytes)?
+    {
+        let id = read_varint(&mut bytes)?;
+        cards.push((id, read_varint(&mut bytes)?));
+    }
+    Some((heroes, cards))
+}
+
+///encode a wild deck code of the game
+fn encode_deckstring(hero:u32, cards:&[(u32, u32)]) -> String
+{
+    let mut bytes = vec![0];
+    write_varint(&mut bytes, 1); //version
+    write_varint(&mut bytes, 1); //wild
+    write_varint(&mut bytes, 1);
+    write_varint(&mut bytes, hero);
+    let mut cards = cards.to_vec();
+    cards.sort();
+    for copies in [1, 2]
+    {
+        let ids = cards.iter().filter(|(_,n)| *n == copies).map(|(id,_)| *id).collect::<Vec<u32>>();
+        write_varint(&mut bytes, ids.len() as u32);
+        ids.into_iter().for_each(|id| write_varint(&mut bytes, id));
+    }
+    let others = cards.iter().filter(|(_,n)| *n > 2).collect::<Vec<&(u32, u32)>>();
+    write_varint(&mut bytes, others.len() as u32);
+    for &&(id, n) in others.iter()
+    {
+        write_varint(&mut bytes, id);
+        write_varint(&mut bytes, n);
+    }
+    base64_encode(&bytes)
+}
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 enum PlayOrder
 {
@@ -494,9 +1733,9 @@ impl PlayOrder
         }
     }
 
-    fn flip_the_coin() -> PlayOrder
+    fn flip_the_coin(rng:&fastrand::Rng) -> PlayOrder
     {
-        match fastrand::bool()
+        match rng.bool()
         {
             true=>PlayOrder::First,
             false=>PlayOrder::Second,
@@ -504,6 +1743,50 @@ impl PlayOrder
     }
 }
 
+///a keep situation of the basic command: keeping the whole hand, compared with keeping only the baseline and tossing the rest
+#[derive(Clone, Debug, PartialEq)]
+struct Scenario
+{
+    label:String,
+    play_order:PlayOrder,
+    hand:Vec<Card>,
+    ///the cards of hand kept by the baseline
+    baseline:Vec<Card>,
+}
+
+impl Scenario
+{
+    ///parse a line like `first | 2 2 3 | 2 3 | having 2 3, keeping 2`: play order, hand, baseline and label.
+    ///the baseline can be empty. a hand with more cards than the start hand is still read, sim_common_pattern skips it
+    fn parse(line:&str) -> Option<Scenario>
+    {
+        let parts = line.split('|').map(|x| x.trim()).collect::<Vec<&str>>();
+        if parts.len() != 4
+        {
+            return None;
+        }
+        let play_order = match parts[0]
+        {
+            "first"=>PlayOrder::First,
+            "second"=>PlayOrder::Second,
+            _=>return None,
+        };
+        let hand = parts[1].split_whitespace().map(Card::create).collect::<Option<Vec<Card>>>()?;
+        let baseline = parts[2].split_whitespace().map(Card::create).collect::<Option<Vec<Card>>>()?;
+        Some(Scenario{label:parts[3].to_string(), play_order, hand, baseline})
+    }
+
+    ///load scenarios from file, one on each line. empty lines and lines starting with # are skipped
+    fn load(filename:&str) -> Result<Vec<Scenario>, String>
+    {
+        let contents = std::fs::read_to_string(filename).map_err(|_| "failed to read file".to_string())?;
+        contents.lines().enumerate()
+            .filter(|(_,line)| !line.trim().is_empty() && !line.trim().starts_with('#'))
+            .map(|(i,line)| Scenario::parse(line).ok_or(format!("failed to read scenario at line {}", i+1)))
+            .collect()
+    }
+}
+
 #[derive(Clone)]
 struct Simulator
 {
@@ -514,8 +1797,41 @@ struct Simulator
     maxturn:u8,
     max_search_depth:u8,
     hero:Hero,
+    ///every hero the hero command can choose. see Hero::load
+    heroes:Vec<Hero>,
     play_order:PlayOrder,
     play_card_bonus:i8,
+    ///scores every turn of the search. BaseEvaluator by default
+    evaluator:Arc<dyn Evaluator>,
+    turn_weights:TurnWeights,
+    mulligan_rule:MulliganRule,
+    ///cards burned this game
+    burned_cards:u32,
+    ///the next fatigue damage is fatigue+1
+    fatigue:u32,
+    ///fatigue damage taken this game
+    fatigue_damage:u32,
+    ///mana not spent on cards in the last turn played
+    mana_waste:i8,
+    ///statistics of the last start_simulation
+    stats:SimStats,
+    ///seed of the random streams. None for a different result every run
+    seed:Option<u64>,
+    ///number of worker threads
+    threads:usize,
+    ///score of every rep of the last start_simulation
+    rep_scores:Vec<f64>,
+    ///the index of the first rep of start_simulation. see start_simulation
+    first_rep:usize,
+    ///solve_mull keeps simulating the top two patterns until they separate or each has this many reps. 0 for never
+    max_cycle_reps:i32,
+    mode:SimMode,
+    lookahead:Lookahead,
+    ///future turn scores already worked out by Lookahead::Exact. cleared every turn
+    memo:HashMap<MemoKey, f64>,
+    ///results of get_all_play_patterns. shared by the clones of the simulator, so all the workers use it
+    play_cache:Arc<RwLock<HashMap<PlayKey, Vec<Vec<Card>>>>>,
+    use_play_cache:bool,
 }
 
 impl Simulator
@@ -530,92 +1846,210 @@ impl Simulator
     }
 
     ///run simulation of a giving hand for cycle_reps times. return averge score.
-    fn start_simulation(&mut self, kept_hand:Vec<usize>, do_print:bool) -> Option<f64>
+    /// 
+    ///tossed_hand:the cards thrown back in the mulligan. see set_start_hand
+    /// 
+    ///statistics of the games (burned cards, fatigue, when the hand and the deck get empty...) are saved in self.stats
+    /// 
+    ///the reps are spread over self.threads workers. every rep gets its own random stream.  
+    ///if self.seed is set, the streams come from the seed, so the result is the same every run, whatever the thread count is.  
+    ///the streams start from rep self.first_rep, so more reps of the same hand get new games
+    /// 
+    ///the score of every rep is saved in self.rep_scores. return None if the start hand can't be set(see set_start_hand)
+    fn start_simulation(&mut self, kept_hand:Vec<usize>, tossed_hand:Vec<usize>, do_print:bool) -> Option<f64>
     {    
-        let result_score = Arc::new(Mutex::new(0.0));
+        let seeder = match self.seed
+        {
+            Some(seed)=>fastrand::Rng::with_seed(seed),
+            None=>fastrand::Rng::new(),
+        };
+        let reps = self.cycle_reps.max(0) as usize;
+        let rep_seeds = (0..self.first_rep+reps).map(|_| seeder.u64(..)).skip(self.first_rep).collect::<Vec<u64>>();
+
+        //the first reps%workers workers do one more rep
+        let workers = self.threads.clamp(1, reps.max(1));
         let mut handles = vec![];
-        for _ in 0..4
+        let mut first_rep = 0;
+        for worker in 0..workers
         {
+            let reps_this_worker = reps/workers + (worker < reps%workers) as usize;
+            let seeds = rep_seeds[first_rep..first_rep+reps_this_worker].to_vec();
+            first_rep += reps_this_worker;
+
             let mut sim = self.clone();
-            let result_score = Arc::clone(&result_score);
             let kept_hand = kept_hand.clone();
+            let tossed_hand = tossed_hand.clone();
             let handle = thread::spawn(move || 
             {
-                let mut score_this_thread = 0.0;
-                for _ in 0..sim.cycle_reps/4
+                let mut scores_this_thread = Vec::new();
+                let mut stats_this_thread = SimStats::new(sim.maxturn);
+                for seed in seeds.into_iter()
                 {
+                    sim.dealer.rng = fastrand::Rng::with_seed(seed);
                     sim.score=0.0;
                     sim.reset();
 
-                    let _result = sim.set_start_hand(&kept_hand);
-                    // if result == false
-                    // {
-                    //     return None;
-                    // }
+                    if !sim.set_start_hand(&kept_hand, &tossed_hand)
+                    {
+                        return None;
+                    }
 
-                    if do_print {eprintln!("\nthe kept_hand is {:?}", sim.dealer.get_hand(&sim.dealer.card_location));}
+                    if do_print {eprintln!("\nthe kept_hand is {:?}", sim.dealer.get_hand(&sim.dealer.card_location, &sim.dealer.turn_state));}
 
 
+                    let mut hand_empty_turn = None;
+                    let mut deck_empty_turn = None;
                     for turn in 1..=sim.maxturn
                     {
                         sim.draw_card();
                         let card_drew = sim.hand.last();
                         if do_print {
-                            println!("in turn {} the draw is {:?} the hand is {:?}", turn, card_drew, sim.dealer.get_hand(&sim.dealer.card_location));
+                            println!("in turn {} the draw is {:?} the hand is {:?}", turn, card_drew, sim.dealer.get_hand(&sim.dealer.card_location, &sim.dealer.turn_state));
                         }
 
-                        let score_a_turn = sim.play_a_turn(None, turn as i8, sim.max_search_depth, do_print);
+                        let depth = sim.horizon_depth(turn, sim.maxturn);
+                        let mana = (sim.dealer.turn_state.crystals(turn) - sim.dealer.turn_state.overload).max(0);
+                        let score_a_turn = sim.play_a_turn(None, None, turn, depth, do_print);
                         sim.score += score_a_turn;
+
+                        let index = turn as usize - 1;
+                        stats_this_thread.cards_in_hand[index] += sim.hand.len() as f64;
+                        if sim.mana_waste <= 0
+                        {
+                            stats_this_thread.curve_out_rate[index] += 1.0;
+                        }
+                        stats_this_thread.mana_used[index] += (mana - sim.mana_waste) as f64;
+                        if hand_empty_turn.is_none() && sim.hand.is_empty()
+                        {
+                            hand_empty_turn = Some(turn);
+                        }
+                        if deck_empty_turn.is_none() && sim.dealer.deck_size(None) == 0
+                        {
+                            deck_empty_turn = Some(turn);
+                        }
+                    }
+                    scores_this_thread.push(sim.score);
+                    stats_this_thread.burned_cards += sim.burned_cards as f64;
+                    stats_this_thread.fatigue_damage += sim.fatigue_damage as f64;
+                    if let Some(turn) = hand_empty_turn
+                    {
+                        stats_this_thread.hand_empty_turn += turn as f64;
+                        stats_this_thread.hand_empty_rate += 1.0;
+                    }
+                    if let Some(turn) = deck_empty_turn
+                    {
+                        stats_this_thread.deck_empty_turn += turn as f64;
+                        stats_this_thread.deck_empty_rate += 1.0;
                     }
-                    score_this_thread += sim.score;
                     if do_print {eprintln!("the score this rep is {}", sim.score);}
                 }
-                let mut score = result_score.lock().unwrap();
-                *score += score_this_thread;
+                Some((scores_this_thread, stats_this_thread))
             });
             handles.push(handle);
         }
 
+        //keep the scores in the order of the reps, so the float sum doesn't depend on the threads
+        let mut scores = Vec::new();
+        let mut stats = SimStats::new(self.maxturn);
         for handle in handles
         {
-            handle.join().unwrap();
+            let (mut scores_this_thread, stats_this_thread) = handle.join().unwrap()?;
+            scores.append(&mut scores_this_thread);
+            stats.merge(&stats_this_thread);
         }
 
-        let result = *result_score.lock().unwrap();
+        stats.average(self.cycle_reps);
+        self.stats = stats;
+        let result = scores.iter().sum::<f64>();
+        self.rep_scores = scores;
         Some(result/self.cycle_reps as f64)
     }
 
 
+    ///the search depth of a turn. never look at the turns after last_turn
+    fn horizon_depth(&self, turn:u8, last_turn:u8) -> u8
+    {
+        self.max_search_depth.min(last_turn.saturating_sub(turn) + 1)
+    }
+
     fn draw_card(&mut self)
     {
-        self.hand.push(self.dealer.draw_card(None));
+        let draw = self.dealer.draw_card(None);
+        self.take_draw(draw);
+    }
+
+    ///put the card drew in hand. count burned cards and fatigue damage
+    fn take_draw(&mut self, draw:DrawResult)
+    {
+        match draw
+        {
+            DrawResult::InHand(p)=>self.hand.push(p),
+            DrawResult::Burned(_)=>self.burned_cards += 1,
+            DrawResult::Fatigue=>
+            {
+                self.fatigue += 1;
+                self.fatigue_damage += self.fatigue;
+            },
+        }
     }
 
     fn new(cycle_reps:i32, maxturn:u8, max_search_depth:u8, play_card_bonus:i8) -> Simulator
     {
+        let heroes = Hero::defaults();
         Simulator{
                     cycle_reps,
                     dealer:Dealer::new(),
                     hand:Vec::new(),
                     score:10.0,
                     maxturn, 
-                    hero:Hero::Warrior,
+                    hero:heroes[0].clone(),
+                    heroes,
                     max_search_depth,
                     play_order:PlayOrder::First,
                     play_card_bonus,
+                    evaluator:Arc::new(BaseEvaluator{play_card_bonus}),
+                    turn_weights:TurnWeights::Equal,
+                    mulligan_rule:MulliganRule::Hearthstone,
+                    burned_cards:0,
+                    fatigue:0,
+                    fatigue_damage:0,
+                    mana_waste:0,
+                    stats:SimStats::default(),
+                    seed:None,
+                    threads:thread::available_parallelism().map(|n| n.get()).unwrap_or(4),
+                    rep_scores:Vec::new(),
+                    first_rep:0,
+                    max_cycle_reps:0,
+                    mode:SimMode::Full,
+                    lookahead:Lookahead::Sample,
+                    memo:HashMap::new(),
+                    play_cache:Arc::new(RwLock::new(HashMap::new())),
+                    use_play_cache:true,
                 }
     }
 
-    fn set_start_hand(&mut self,kept_hand:&Vec<usize>) -> bool
+    ///set the start hand after the mulligan
+    /// 
+    ///kept_hand:cards kept from the opening hand
+    /// 
+    ///tossed_hand:cards thrown back. with MulliganRule::Hearthstone they are kept out of the replacement draw, then shuffled back
+    ///
+    ///return false if a card is not in the deck, or there is no card slot left for the coin or the starting card
+    fn set_start_hand(&mut self, kept_hand:&[usize], tossed_hand:&[usize]) -> bool
     {
         //reset deck. if there is coin in the deck, delete it.
         self.dealer.reset_deck();
         match self.dealer.cards.iter().position(|x| x.mana == -1)
         {
-            Some(p)=>{self.dealer.cards.remove(p);self.dealer.card_location.remove(p);},
+            Some(p)=>{self.dealer.cards.remove(p);self.dealer.card_location.remove(p);self.dealer.turn_state.remove(p);},
             None=>(),
         };
 
+        if kept_hand.len() > self.play_order.get_start_hand_size() as usize
+        {
+            return false;
+        }
+
         //draw the kept hand from deck.
         for &card_pos in kept_hand.iter()
         {
@@ -629,18 +2063,43 @@ impl Simulator
         //set kept_hand
         self.hand = kept_hand.to_vec();
 
+        //put the tossed cards aside
+        if self.mulligan_rule == MulliganRule::Hearthstone && !self.dealer.set_aside(tossed_hand)
+        {
+            return false;
+        }
+
         //draw the rest
         for _ in 0..self.play_order.get_start_hand_size() as usize - kept_hand.len()
         {
             self.draw_card();
         }
 
+        //shuffle the tossed cards back
+        self.dealer.shuffle_back();
+
+        self.dealer.turn_state.extra_crystals = self.hero.starting_mana as i16;
+
+        //put the starting card of the hero into hand
+        if let Some(card) = self.hero.starting_card.clone()
+        {
+            let pos = match self.dealer.give_card(card)
+            {
+                Some(p)=>p,
+                None=>return false,
+            };
+            self.dealer.start_card = Some(pos);
+            self.hand.push(pos);
+        }
+
         //add coin according to play order
         if self.play_order == PlayOrder::Second
         {
-            self.dealer.insert_card(Card{mana:-1, card_power:CardPower::Normal});
-            *self.dealer.card_location.last_mut().unwrap() = CardLocation::InHand;
-            self.hand.push(self.dealer.cards.len()-1);
+            match self.dealer.give_card(Card::new(-1, CardPower::Normal))
+            {
+                Some(p)=>self.hand.push(p),
+                None=>return false,
+            }
         }
         true
     }
@@ -652,24 +2111,84 @@ impl Simulator
     ///no duplicate plays
     /// 
     ///if a play is returned , the subset of play should not be returned. example:if [2,3] is valid, then don't try play [], [2], [3]
+    /// 
+    ///the result only depends on the cards, so it's cached by (sorted hand, mana_max, mana_min) if use_play_cache is set
     fn get_all_play_patterns(&self, hand:Vec<Card>, mana_max:i8, mana_min:i8) -> Vec<Vec<Card>>
     {
-        //take 0-mana cards out since they are always gonna be played
-        let no_zero_hand = hand.clone().into_iter().filter(|x| x.mana != 0).collect::<Vec<Card>>();
-        let zero_mana_cards = hand.clone().into_iter().filter(|x| x.mana == 0).collect::<Vec<Card>>();
+        let mut hand = hand;
+        hand.sort();
+        let key = (hand, mana_max, mana_min);
+        if self.use_play_cache
+        {
+            if let Some(plays) = self.play_cache.read().unwrap().get(&key)
+            {
+                return plays.clone();
+            }
+        }
+        let hand = &key.0;
+
+        //the search works with the mana really spent, sorted by it. cards giving mana (like the coin) cost less than 0
+        //(the card with the mana really spent, the card in hand)
+        let mut net_hand = hand.iter().map(|x| (Card{mana:x.net_cost(), ..x.clone()}, x.clone())).collect::<Vec<(Card, Card)>>();
+        net_hand.sort();
+
+        //take 0-mana cards out since they are always gonna be played. not a card giving mana, its printed cost can be more than 0
+        let always_played = |x:&Card| x.mana == 0 && x.temp_mana() == 0;
+        let no_zero_hand = net_hand.iter().filter(|(x,_)| !always_played(x)).map(|(x,_)| x.clone()).collect::<Vec<Card>>();
+        let zero_mana_cards = net_hand.iter().filter(|(x,_)| always_played(x)).map(|(_,c)| c.clone()).collect::<Vec<Card>>();
         
         let mut patterns_for_now = self.get_all_plays(no_zero_hand, mana_max, mana_min);
         patterns_for_now = Simulator::remove_duplicate_plays(patterns_for_now);
         Simulator::add_play_nothing(&mut patterns_for_now);
 
-        //put those 0-mana cards back
+        //back to the cards in hand, then put those 0-mana cards back
         for pattern in patterns_for_now.iter_mut()
         {
+            for card in pattern.iter_mut()
+            {
+                *card = net_hand.iter().find(|(x,_)| x == card).unwrap().1.clone();
+            }
             pattern.append(&mut zero_mana_cards.clone());
         }
+
+        if self.use_play_cache
+        {
+            let mut cache = self.play_cache.write().unwrap();
+            if cache.len() >= PLAY_CACHE_LIMIT
+            {
+                cache.clear();
+            }
+            cache.insert(key, patterns_for_now.clone());
+        }
         patterns_for_now
     }
 
+    ///every play of a turn: the cards played, and every number of hero power uses that fits with them.
+    ///the mana of the hero power is taken before the cards, so using it instead of a card is tried too.
+    ///a hero power with no value is never used
+    fn get_all_turn_plays(&self, hand:&[Card], mana:i8) -> Vec<(Vec<Card>, Vec<u8>)>
+    {
+        let max_uses = match self.hero.hero_power.value > 0.0
+        {
+            true=>self.hero.hero_power.max_uses(mana),
+            false=>0,
+        };
+        let mut plays:Vec<(Vec<Card>, Vec<u8>)> = Vec::new();
+        for uses in 0..=max_uses
+        {
+            let mana_for_cards = mana - uses as i8 * self.hero.hero_power.cost.max(0);
+            for play in self.get_all_play_patterns(hand.to_vec(), mana_for_cards, 0).into_iter()
+            {
+                match plays.iter_mut().find(|(p,_)| p == &play)
+                {
+                    Some((_,all_uses))=>all_uses.push(uses),
+                    None=>plays.push((play, vec![uses])),
+                }
+            }
+        }
+        plays
+    }
+
     ///a step for function get_all_play_patterns
     fn get_all_plays(&self, hand:Vec<Card>, mana_max:i8, mana_min:i8) -> Vec<Vec<Card>>
     {
@@ -691,27 +2210,37 @@ impl Simulator
             return Vec::new();
         }
 
-        let have_coin = match hand.iter().position(|x| x.mana==-1)
-        {
-            Some(p)=>{hand.swap_remove(p);true},
-            None=>false,
-        };
-
-
-        hand = hand.into_iter().filter(|x| x.mana<=mana_max+1).collect();
-        //hand.sort_by(|a,b| a.mana.cmp(&b.mana));
-
-        let mut patterns_for_now = Vec::new();
-        if have_coin
+        //a card giving mana (like the coin): it's played before the other cards, the cheapest first.
+        //its printed cost has to fit the mana there is, then the plays using it can spend more than mana_max
+        if let Some(p) = (0..hand.len()).filter(|&i| hand[i].temp_mana() > 0).min_by_key(|&i| hand[i].mana + hand[i].temp_mana())
         {
-            patterns_for_now = self.get_all_plays(hand.clone(), mana_max+1, mana_max+1);
-            for pattern in patterns_for_now.iter_mut()
+            let cheat = hand.swap_remove(p);
+            let mut patterns_for_now = Vec::new();
+            if cheat.mana + cheat.temp_mana() <= mana_max
             {
-                pattern.push(Card{mana:-1, card_power:CardPower::Normal});
+                //a play spending no more than mana_max doesn't need the coin, unless another mana card needs the mana to be cast
+                let min = match cheat.mana < 0 && hand.iter().all(|x| x.temp_mana() == 0)
+                {
+                    true=>mana_max+1,
+                    false=>mana_min - cheat.mana,
+                };
+                patterns_for_now = self.get_all_plays(hand.clone(), mana_max - cheat.mana, min);
+                for pattern in patterns_for_now.iter_mut()
+                {
+                    pattern.push(cheat.clone());
+                }
+                if patterns_for_now.is_empty() && cheat.mana >= 0 && cheat.mana >= mana_min
+                {
+                    patterns_for_now.push(vec![cheat.clone()]);
+                }
             }
             //eprintln!("the patterns_with_coin is {:?}", patterns_for_now);
+            patterns_for_now.append(&mut self.get_all_plays(hand, mana_max, mana_min));
+            return patterns_for_now;
         }
 
+        let mut patterns_for_now = Vec::new();
+
         //take cards that not bigger than mana_max
         hand = hand.into_iter().filter(|x| x.mana<=mana_max).collect();
         if hand.is_empty()
@@ -753,7 +2282,7 @@ impl Simulator
             (false,true) => 0,
             (false,false) => 
             {
-                if last.mana == hand.last().unwrap().mana && last.card_power != hand.last().unwrap().card_power
+                if last.mana == hand.last().unwrap().mana && &last != hand.last().unwrap()
                 {
                     mana_max - last.mana + 1
                 }
@@ -781,7 +2310,10 @@ impl Simulator
         {
             map.insert(play);
         }
-        map.into_iter().collect::<Vec<Vec<Card>>>()
+        //sort them, so the plays are tried in the same order every run
+        let mut plays = map.into_iter().collect::<Vec<Vec<Card>>>();
+        plays.sort();
+        plays
     }
 
     ///a step for function "get_all_play_patterns"
@@ -798,23 +2330,55 @@ impl Simulator
 
     ///play a turn
     /// 
-    /// try every reasonable play according to hand(get from card_locations) and mana
+    /// try every reasonable play according to hand(get from card_locations) and the mana crystals of the turn
     /// 
     /// look forward for depth turns
     /// 
-    /// do the play with highest score
-    fn play_a_turn(&mut self, card_locations:Option<&Vec<CardLocation>>, mana:i8, depth:u8, do_print:bool) -> f64
+    /// do the play with highest score. every turn is scored by self.evaluator
+    /// 
+    /// with Lookahead::Exact every different draw of the future turns is tried, and the scores are memoized.
+    /// draws from card effects are still random
+    fn play_a_turn(&mut self, card_locations:Option<&CardLocations>, turn_state:Option<&TurnState>, turn:u8, depth:u8, do_print:bool) -> f64
     {
         let do_orignal = match card_locations
         {
             Some(_)=>false,
             None=>true,
         };
+        if do_orignal
+        {
+            self.memo.clear();
+        }
 
         let card_locations = match card_locations
         {
-            Some(x)=>x.to_vec(),
-            None=>self.dealer.card_location.clone(),
+            Some(x)=>*x,
+            None=>self.dealer.card_location,
+        };
+        let turn_state = match turn_state
+        {
+            Some(x)=>*x,
+            None=>self.dealer.turn_state,
+        };
+        let mana = turn_state.crystals(turn);
+
+        let memo_key = match (self.lookahead, do_orignal || do_print)
+        {
+            (Lookahead::Exact, false)=>
+            {
+                let mut hand = self.dealer.get_hand(&card_locations, &turn_state);
+                hand.sort();
+                let mut deck = card_locations.positions(CardLocation::InDeck).map(|p| self.dealer.cards[p].clone())
+                                    .collect::<Vec<Card>>();
+                deck.sort();
+                let key = (hand, deck, turn, mana, turn_state.overload, depth);
+                if let Some(&score) = self.memo.get(&key)
+                {
+                    return score;
+                }
+                Some(key)
+            },
+            _=>None,
         };
 
         if do_print && depth == self.max_search_depth
@@ -828,15 +2392,15 @@ impl Simulator
                     break;
                 }
             }
-            println!("\nin turn {},the draw is [{:?}] ,the hand is :{:?}", mana, self.dealer.cards[card_drew_pos], self.dealer.get_hand(&card_locations));
+            println!("\nin turn {},the draw is [{:?}] ,the hand is :{:?}", turn, self.dealer.cards[card_drew_pos], self.dealer.get_hand(&card_locations, &turn_state));
         }
 
-        let hand:Vec<Card> = self.dealer.cards.clone().into_iter().zip(card_locations.iter())
-                                    .filter(|(_,lo)| lo == &&CardLocation::InHand).map(|(c,_)|c)
-                                    .collect();
+        let hand:Vec<Card> = self.dealer.get_hand(&card_locations, &turn_state);
 
         //get all reasonable plays
-        let all_plays = self.get_all_play_patterns(hand.clone(), mana, 0);
+        //the mana left after the overload of the last turn
+        let mana_available = (mana - turn_state.overload).max(0);
+        let all_plays = self.get_all_turn_plays(&hand, mana_available);
 
         //println!("in turn {}, the hand is {:?}, all patterns are {:?}", mana, hand, all_plays);
         if do_print && depth == self.max_search_depth
@@ -845,59 +2409,65 @@ impl Simulator
         }
         let mut max_score = -10.0;
         let mut best_play = Vec::new();
+        let mut best_hero_power_uses = 0;
 
 
         //try every play 
-        for play in all_plays.into_iter()
+        for (play, all_uses) in all_plays.into_iter()
         {
-            if do_print
-            {
-                eprintln!("trying play {:?}", play);
-            }
-
-            let mut score = 10.0;
+            let mana_waste:i8 = mana_available - play.iter().map(|x| x.net_cost()).sum::<i8>();
+            //the future is the same whatever the hero power does, so only the best number of uses is searched
+            let (mut score, hero_power_uses) = all_uses.into_iter()
+                                                .map(|uses| (self.weighted_turn_score(&play, mana_waste, uses, turn), uses))
+                                                .fold((f64::MIN, 0), |best, x| if x.0 > best.0 {x} else {best});
 
-
-            let mana_waste:i8 = mana as i8 - play.iter().map(|x| x.mana).sum::<i8>();
-            score -= mana_waste as f64;
-
-
-            if (self.hero == Hero::DemonHunter && mana_waste == 1 && mana != 1) || (self.hero != Hero::DemonHunter && mana_waste == 2)
+            if do_print
             {
-                if do_print {eprintln!("use hero power");}
-                score += self.hero.hero_power_value();
+                eprintln!("trying play {:?}, hero power x{}", play, hero_power_uses);
             }
 
 
             //play those cards
-            let mut result_card_location = card_locations.clone();
+            let mut result_card_location = card_locations;
+            let mut result_turn_state = turn_state;
             for card in play.iter()
             {
-                //eprintln!("playing card {:?}, hand is {:?}", card, self.dealer.get_hand(&result_card_location));
-                let card_pos = self.dealer.get_card_pos(card.clone(), &CardLocation::InHand, Some(&result_card_location)).unwrap();
-                score += self.play_a_card(card_pos, Some(&mut result_card_location));
+                //eprintln!("playing card {:?}, hand is {:?}", card, self.dealer.get_hand(&result_card_location, &result_turn_state));
+                let card_pos = self.dealer.get_card_pos(card.clone(), &CardLocation::InHand, Some(&result_card_location), Some(&result_turn_state)).unwrap();
+                self.play_a_card(card_pos, Some(&mut result_card_location));
             }
+            self.dealer.end_turn(&play, turn, Some(&result_card_location), Some(&mut result_turn_state));
 
 
 
             
-            let mut score_sum = 0.0;
+            let mut score_future = 0.0;
 
-            //try 10 draws, take average
-            if depth > 1  
+            if depth > 1 && self.lookahead == Lookahead::Sample
             {
+                //try 10 draws, take average
+                let mut score_sum = 0.0;
                 for _ in 0..10
                 {
-                    let mut locations_temp = result_card_location.clone();
+                    let mut locations_temp = result_card_location;
                     self.dealer.draw_card(Some(&mut locations_temp));
                     let print_next_turn =false;
-                    let future_turn_score = self.play_a_turn(Some(&locations_temp), mana+1, depth-1, print_next_turn);
+                    let future_turn_score = self.play_a_turn(Some(&locations_temp), Some(&result_turn_state), turn+1, depth-1, print_next_turn);
                     score_sum += future_turn_score;
                 }
+                score_future = score_sum/10.0;
+            }
+            else if depth > 1 && self.lookahead == Lookahead::Exact
+            {
+                //try every different draw, weighted by its chance
+                for (chance, locations_temp) in self.dealer.draw_outcomes(result_card_location).into_iter()
+                {
+                    let print_next_turn =false;
+                    score_future += chance * self.play_a_turn(Some(&locations_temp), Some(&result_turn_state), turn+1, depth-1, print_next_turn);
+                }
             }
 
             let score_this = score;
-            let score_future = score_sum/10.0;
             score = score_this + score_future;
 
 
@@ -906,6 +2476,7 @@ impl Simulator
             if score > max_score
             {
                 best_play = play;
+                best_hero_power_uses = hero_power_uses;
                 max_score = score;
             }
         }
@@ -913,8 +2484,8 @@ impl Simulator
         if do_print
         {
             println!("the best play of hand {:?} in turn {} is: {:?}", 
-                            self.dealer.get_hand(&card_locations), 
-                            mana, 
+                            self.dealer.get_hand(&card_locations, &turn_state), 
+                            turn, 
                             best_play
                     );
         }
@@ -923,65 +2494,95 @@ impl Simulator
         //do the best play
         if do_orignal == true
         {
-            max_score = 10.0;
-
-            let mana_waste:i8 = mana as i8 - best_play.iter().map(|x| x.mana).sum::<i8>();
-            max_score -= mana_waste as f64;
+            let mana_waste:i8 = mana_available - best_play.iter().map(|x| x.net_cost()).sum::<i8>();
+            max_score = self.weighted_turn_score(&best_play, mana_waste, best_hero_power_uses, turn);
+            self.mana_waste = mana_waste;
 
 
-            if (self.hero == Hero::DemonHunter && mana_waste == 1 && mana != 1) || (self.hero != Hero::DemonHunter && mana_waste == 2)
+            if do_print && best_hero_power_uses > 0
             {
-                if do_print {println!("use hero power");}
-                max_score += self.hero.hero_power_value();
+                println!("use hero power x{}", best_hero_power_uses);
             }
 
             for card in best_play.iter()
             {
-                let card_pos = self.dealer.get_card_pos(card.clone(), &CardLocation::InHand, None).unwrap();
-                max_score += self.play_a_card(card_pos, None);
+                let card_pos = self.dealer.get_card_pos(card.clone(), &CardLocation::InHand, None, None).unwrap();
+                self.play_a_card(card_pos, None);
             }
+            self.dealer.end_turn(&best_play, turn, None, None);
+        }
+
+        if let Some(key) = memo_key
+        {
+            self.memo.insert(key, max_score);
         }
         max_score
     }
 
+    ///the weighted score of a turn. mana_left:the mana not spent on the cards(the hero power mana included)
+    fn weighted_turn_score(&self, play:&[Card], mana_left:i8, hero_power_uses:u8, turn:u8) -> f64
+    {
+        let mana_left = mana_left - hero_power_uses as i8 * self.hero.hero_power.cost.max(0);
+        self.evaluator.score_turn(play, mana_left, &self.hero, hero_power_uses, turn) * self.turn_weights.weight(turn)
+    }
+
     ///show how to play a hand without changing any data in Simulator
-    fn play_a_hand(&mut self, hand:Vec<Card>, mana:i8, do_print:bool)
+    fn play_a_hand(&mut self, hand:Vec<Card>, turn:u8, do_print:bool)
     {
-        let mut locations = vec![CardLocation::InDeck].repeat(self.dealer.card_location.len());
+        let mut locations = CardLocations::new_deck(self.dealer.cards.len());
         for card in hand.iter()
         {
-            if card.mana == -1
-            {
-                locations.push(CardLocation::InHand);
-            }
-            let pos = self.dealer.cards.iter().zip(locations.iter()).position(|(c,l)| l==&CardLocation::InDeck && c == card).unwrap();
-            locations[pos] = CardLocation::InHand;
+            let pos = self.dealer.get_card_pos(card.clone(), &CardLocation::InDeck, Some(&locations), None).unwrap();
+            locations.set(pos, CardLocation::InHand);
         }
-        self.play_a_turn(Some(&locations), mana, self.max_search_depth, do_print);
+        self.play_a_turn(Some(&locations), Some(&TurnState::default()), turn, self.max_search_depth, do_print);
     }
 
 
     ///if card_location is None, use the orignal one (self.dealer.card_location). change self.hand only if it's the orignal.
-    fn play_a_card(&mut self, pos_in_deck:usize, card_locations:Option<&mut Vec<CardLocation>>) -> f64
+    /// 
+    ///the effects of the card (draw, discover) are done here, so the cards got are in hand right after the play
+    fn play_a_card(&mut self, pos_in_deck:usize, mut card_locations:Option<&mut CardLocations>)
     {
         //if it's the orignal one ,change the hand in simulator also.
-        if card_locations == None
+        let is_orignal = card_locations.is_none();
+        if is_orignal
         {
             self.hand.swap_remove(self.hand.iter().position(|x| x == &pos_in_deck).unwrap());
         }
 
-        let locations = match card_locations
+        match card_locations.as_deref_mut()
         {
-            Some(x)=>x,
-            None=>&mut self.dealer.card_location,
+            Some(x)=>x.set(pos_in_deck, CardLocation::NoWhere),
+            None=>self.dealer.card_location.set(pos_in_deck, CardLocation::NoWhere),
         };
-        locations[pos_in_deck] = CardLocation::NoWhere;
 
-        match self.dealer.cards[pos_in_deck].card_power
+        let effects = self.dealer.cards[pos_in_deck].effects.clone();
+        for effect in effects.iter()
         {
-            CardPower::Strong=>0.5 + self.play_card_bonus as f64,
-            CardPower::Normal=>0.0 + self.play_card_bonus as f64,
-            CardPower::Weak=>-0.5 + self.play_card_bonus as f64,
+            let num = match effect
+            {
+                CardEffect::Draw(n)=>*n,
+                CardEffect::Discover=>1,
+                //mana, cost, overload and crystal effects are done by get_all_play_patterns and end_turn
+                CardEffect::Mana(_) | CardEffect::Discount(_) | CardEffect::Cheaper(_) | CardEffect::Overload(_)
+                    | CardEffect::EmptyCrystal(_) | CardEffect::FullCrystal(_) | CardEffect::DestroyCrystal(_)=>0,
+            };
+            for _ in 0..num
+            {
+                let draw = match effect
+                {
+                    CardEffect::Draw(_)=>self.dealer.draw_card(card_locations.as_deref_mut()),
+                    //nothing to discover from an empty deck
+                    CardEffect::Discover if self.dealer.deck_size(card_locations.as_deref()) == 0=>continue,
+                    CardEffect::Discover=>self.dealer.discover_card(card_locations.as_deref_mut()),
+                    _=>continue,
+                };
+                if is_orignal
+                {
+                    self.take_draw(draw);
+                }
+            }
         }
     }
 
@@ -991,50 +2592,33 @@ impl Simulator
     {
         self.dealer.reset_deck();
         self.hand = Vec::new();
+        self.burned_cards = 0;
+        self.fatigue = 0;
+        self.fatigue_damage = 0;
     }
 
+    ///set the hero by its name or one of its aliases
     fn set_hero(&mut self, word: &str) -> CommandResult
     {
-        match word 
-        {
-            "wr" | "warrior"=>self.hero=Hero::Warrior,
-            "wl" | "warlock"=>self.hero=Hero::Warlock,
-            "pr" | "priest"=>self.hero=Hero::Priest,
-            "dr" | "druid"=>self.hero=Hero::Druid,
-            "ma" | "mage"=>self.hero=Hero::Mage,
-            "pa" | "paladin"=>self.hero=Hero::Paladin,
-            "sh" | "shaman"=>self.hero=Hero::Shaman,
-            "ro" | "rogue"=>self.hero=Hero::Rogue,
-            "hu" | "hunter"=>self.hero=Hero::Hunter,
-            "dh" => self.hero=Hero::DemonHunter,
-            _=>return CommandResult::Err("it's not a hero".to_string()),
-        }
-        println!("the hero is set to {:?}", self.hero);
+        match self.heroes.iter().find(|x| x.is_called(word))
+        {
+            Some(hero)=>self.hero = hero.clone(),
+            None=>return CommandResult::Err("it's not a hero".to_string()),
+        }
+        println!("the hero is set to {}", self.hero.name);
         CommandResult::Ok
     }
 
 
-    ///input a hand. print all possible mulligan score
-    /// 
-    ///return the best move. 
-    /// 
-    ///example:return '101' means keep the first and third card
-    fn solve_mull(&mut self, hand:Vec<usize>) -> String
+    ///all different keep patterns of a hand(sorted). patterns keeping the same cards are tried only once
+    fn keep_patterns(&self, hand:&[usize]) -> Vec<KeepPattern>
     {
-        // let hand_size = self.play_order.get_start_hand_size();
-
-        //if hand-length is lower than hand size , fill the hand with high-cost card
-        // hand.append(&mut Card::create_cards(format!("{}n10", hand_size as usize - hand.len()).as_str()).unwrap());
-        
-        let mut hand = hand.clone();
-        hand.sort();
-
-        //try all patterns of mulligan
-        let mut result = Vec::new();
+        let mut patterns = Vec::new();
         let mut hand_set = std::collections::HashSet::new();
         for index in 0..self.play_order.get_pattern_int()
         {
             let mut result_hand = Vec::new();
+            let mut tossed_hand = Vec::new();
             let pattern = match self.play_order
             {
                 PlayOrder::First=>format!("{:03b}", index).chars().collect::<String>(),
@@ -1044,39 +2628,150 @@ impl Simulator
             {
                 if c == '1'
                 {
-                    result_hand.push(hand[i].clone());
+                    result_hand.push(hand[i]);
+                }
+                else
+                {
+                    tossed_hand.push(hand[i]);
                 }
             }
             let hand_by_cards = self.dealer.position_to_cards(&result_hand);
-            let already_tested = !hand_set.insert(hand_by_cards.clone());
-            let pass_string = match already_tested
+            if hand_set.insert(hand_by_cards.clone())
             {
-                true => "(pass)",
-                false => "",
-            };
-            println!("{}/{}{}", index+1, self.play_order.get_pattern_int(), pass_string);
+                patterns.push(KeepPattern{
+                                            pattern,
+                                            kept:result_hand,
+                                            tossed:tossed_hand,
+                                            cards:hand_by_cards,
+                                            scores:Vec::new(),
+                                            stats:SimStats::default(),
+                                        });
+            }
+        }
+        patterns
+    }
+
+    ///simulate reps more games for every pattern, in parallel. the threads are shared among them
+    /// 
+    ///the new scores are added to the old ones. the stats are of the last games
+    fn simulate_patterns(&self, patterns:&mut [KeepPattern], reps:i32)
+    {
+        if patterns.is_empty()
+        {
+            return;
+        }
+        let threads_each = (self.threads / patterns.len()).max(1);
+        thread::scope(|scope|
+        {
+            for keep in patterns.iter_mut()
+            {
+                let mut sim = self.clone();
+                sim.threads = threads_each;
+                sim.cycle_reps = reps;
+                sim.first_rep = keep.scores.len();
+                scope.spawn(move ||
+                {
+                    let do_print = false;
+                    sim.start_simulation(keep.kept.clone(), keep.tossed.clone(), do_print);
+                    keep.scores.append(&mut sim.rep_scores);
+                    keep.stats = sim.stats;
+                });
+            }
+        });
+    }
 
-            if !already_tested
+    ///race the patterns: simulate them in RACING_ROUNDS rounds, after every round drop the patterns whose
+    ///confidence interval is all below the leader's.
+    ///
+    ///the budget is cycle_reps reps for every pattern. every round spends an even share of the budget left on the patterns
+    ///still racing, so the reps of each pattern grow as the field shrinks, and the last round spends the rest
+    fn race_patterns(&self, patterns:Vec<KeepPattern>) -> Vec<KeepPattern>
+    {
+        let mut budget = patterns.len() as i32 * self.cycle_reps.max(0);
+        let mut alive = patterns;
+        let mut dropped = Vec::new();
+        for round in 0..RACING_ROUNDS
+        {
+            if alive.is_empty() || budget < alive.len() as i32
             {
-                let do_print = false;
-                let score = self.start_simulation(result_hand.clone(), do_print).unwrap();
-                // println!("the score of {:?} is :{:.3}", self.dealer.position_to_cards(&result_hand), score);
-                result.push((pattern,score,hand_by_cards));
+                break;
+            }
+            let reps = (budget / (RACING_ROUNDS - round) / alive.len() as i32).max(1);
+            self.simulate_patterns(&mut alive, reps);
+            budget -= reps * alive.len() as i32;
+
+            let leader_low = alive.iter().map(|x| x.confidence_interval().0).fold(f64::MIN, f64::max);
+            let (keep, drop):(Vec<KeepPattern>, Vec<KeepPattern>) = alive.into_iter().partition(|x| x.confidence_interval().1 >= leader_low);
+            alive = keep;
+            dropped.extend(drop);
+        }
+        if !dropped.is_empty()
+        {
+            println!("racing dropped {} patterns early", dropped.len());
+        }
+        dropped.extend(alive);
+        dropped
+    }
+
+    ///simulate every keep pattern of the hand. return them from the worst to the best
+    ///
+    ///hand:positions of the start hand, sorted
+    fn rank_patterns(&mut self, hand:&[usize], do_print:bool) -> Vec<KeepPattern>
+    {
+        //try all patterns of mulligan
+        let mut result = self.keep_patterns(hand);
+        if do_print {println!("simulating {} patterns ({} same patterns passed)", result.len(), self.play_order.get_pattern_int() as usize - result.len());}
+        match self.mode
+        {
+            SimMode::Full=>self.simulate_patterns(&mut result, self.cycle_reps),
+            SimMode::Racing=>result = self.race_patterns(result),
+        }
+        result.sort_by(|a,b| a.mean().partial_cmp(&b.mean()).unwrap());
+
+        //keep simulating the top two until they separate
+        while result.len() >= 2 && (result[result.len()-1].scores.len() as i32) < self.max_cycle_reps
+        {
+            let len = result.len();
+            let reps = self.cycle_reps.min(self.max_cycle_reps - result[len-1].scores.len() as i32);
+            //no scores or no reps to add: more reps can't separate them
+            if reps <= 0 || result[len-1].scores.is_empty() || !result[len-1].is_tied_with(&result[len-2])
+            {
+                break;
             }
+            if do_print {println!("the top two are tied, simulating {} more reps", reps);}
+            self.simulate_patterns(&mut result[len-2..], reps);
+            result.sort_by(|a,b| a.mean().partial_cmp(&b.mean()).unwrap());
         }
-        result.sort_by(|a,b| a.1.partial_cmp(&b.1).unwrap());
+        result
+    }
+
+    ///input a hand. print all possible mulligan score
+    /// 
+    ///return the best move. 
+    /// 
+    ///example:return '101' means keep the first and third card
+    fn solve_mull(&mut self, hand:Vec<usize>) -> String
+    {
+        let mut hand = hand.clone();
+        hand.sort();
+
+        let result = self.rank_patterns(&hand, true);
 
-        let total_score = 10.0 * self.maxturn as f64;
-        println!("the total base score is {}, {} for every mana waste. +{} for every card played", total_score.to_string().yellow(), "-1".to_string().red(), self.play_card_bonus.to_string().green());
+        let total_score = (1..=self.maxturn).map(|turn| 10.0 * self.turn_weights.weight(turn)).sum::<f64>();
+        println!("the total base score is {}, {} for every mana waste. +{} for every card played. turn weights: {}", total_score.to_string().yellow(), "-1".to_string().red(), self.play_card_bonus.to_string().green(), self.turn_weights);
         for line in result.iter()
         {
-            println!("the score of {:?} is :{}", line.2, format!("{:.3}", line.1).yellow());
+            let (low, high) = line.confidence_interval();
+            println!("the score of {:?} is :{} (se {:.3}, 95% CI {:.3}~{:.3}, {} reps)", line.cards, format!("{:.3}", line.mean()).yellow(), line.std_error(), low, high, line.scores.len());
+            if line.stats.burned_cards > 0.0 || line.stats.fatigue_damage > 0.0
+            {
+                println!("    burned cards:{:.3}, fatigue damage:{:.3} per game", line.stats.burned_cards, line.stats.fatigue_damage);
+            }
         }
         let the_best = result.last().unwrap();
-        // let the_best = result.into_iter().max_by(|x,y|x.1.partial_cmp(&y.1).unwrap()).unwrap();
 
         println!("the best move is:");
-        for (&p,k) in hand.iter().zip(the_best.0.chars())
+        for (&p,k) in hand.iter().zip(the_best.pattern.chars())
         {
             print!("card:{:?}  ", self.dealer.cards[p]);
             if k=='0'
@@ -1089,8 +2784,275 @@ impl Simulator
             }
         }
         self.dealer.reset_deck();
-        println!("the score is {:.3}", the_best.1);
-        the_best.0.clone()
+        println!("the score is {:.3}", the_best.mean());
+        if result.len() >= 2 && the_best.is_tied_with(&result[result.len()-2])
+        {
+            println!("{} with {:?}", "statistically tied".yellow(), result[result.len()-2].cards);
+        }
+        the_best.pattern.clone()
+    }
+
+    ///solve the mulligan of every different start hand of the deck, going first and second. print the keep/toss table
+    ///
+    ///the expected score of each play order is the score of every hand weighted by its chance. the coin flip is the average of the two
+    fn mulligan_guide(&mut self) -> CommandResult
+    {
+        if self.dealer.cards.iter().filter(|x| x.mana != -1).count() < PlayOrder::Second.get_start_hand_size() as usize
+        {
+            return CommandResult::Err("the deck is too small".to_string());
+        }
+        let mut order_scores = Vec::new();
+        for order in [PlayOrder::First, PlayOrder::Second]
+        {
+            let hand_types = self.dealer.hand_types(order.get_start_hand_size() as usize);
+            println!("\ngoing {:?}, {} different start hands", order, hand_types.len());
+            let mut expected_score = 0.0;
+            for (hand, chance) in hand_types.into_iter()
+            {
+                self.reset();
+                self.set_play_order(order.clone());
+                let mut positions = match self.dealer.cards_to_position(hand.clone(), CardLocation::InDeck, None)
+                {
+                    Some(p)=>p,
+                    None=>continue,
+                };
+                positions.sort();
+                let result = self.rank_patterns(&positions, false);
+                let the_best = result.last().unwrap();
+                let tossed = self.dealer.position_to_cards(&the_best.tossed);
+                println!("{:?} ({:.2}%): keep {} toss {} score {}",
+                            hand,
+                            chance * 100.0,
+                            format!("{:?}", the_best.cards).green(),
+                            format!("{:?}", tossed).red(),
+                            format!("{:.3}", the_best.mean()).yellow());
+                expected_score += chance * the_best.mean();
+            }
+            println!("going {:?}, the expected score is {}", order, format!("{:.3}", expected_score).yellow());
+            order_scores.push(expected_score);
+        }
+        self.dealer.reset_deck();
+        println!("\nthe expected score of the coin flip is {}", format!("{:.3}", order_scores.iter().sum::<f64>() / 2.0).yellow());
+        CommandResult::Ok
+    }
+
+    ///how much keeping each different card is worth: the score of keeping it minus the score of tossing it.
+    ///sorted from the best to keep
+    ///
+    ///it's averaged over KEEP_VALUE_HANDS random start hands with the card. the other cards of the hand are tossed both ways,
+    ///and both ways play the same random games
+    fn keep_values(&mut self, order:PlayOrder) -> Result<Vec<(Card, f64)>, String>
+    {
+        let hand_size = order.get_start_hand_size() as usize;
+        let mut kinds = self.dealer.cards.iter().filter(|x| x.mana != -1).cloned().collect::<Vec<Card>>();
+        if kinds.len() < hand_size
+        {
+            return Err("the deck is too small".to_string());
+        }
+        self.reset();
+        self.set_play_order(order.clone());
+        let seed = self.seed;
+        kinds.sort();
+        kinds.dedup();
+
+        let mut values = Vec::new();
+        for card in kinds.into_iter()
+        {
+            let card_pos = self.dealer.get_card_pos(card.clone(), &CardLocation::InDeck, None, None).unwrap();
+            let others = (0..self.dealer.cards.len()).filter(|&p| p != card_pos && self.dealer.cards[p].mana != -1).collect::<Vec<usize>>();
+            let mut value = 0.0;
+            for _ in 0..KEEP_VALUE_HANDS
+            {
+                let mut rest = others.clone();
+                let tossed = (1..hand_size.min(rest.len()+1)).map(|_| rest.swap_remove(self.dealer.rng.usize(..rest.len()))).collect::<Vec<usize>>();
+                let mut tossed_all = tossed.clone();
+                tossed_all.push(card_pos);
+
+                self.seed = Some(self.dealer.rng.u64(..));
+                let do_print = false;
+                let keep = self.start_simulation(vec![card_pos], tossed, do_print).unwrap();
+                let toss = self.start_simulation(Vec::new(), tossed_all, do_print).unwrap();
+                value += keep - toss;
+            }
+            values.push((card, value / KEEP_VALUE_HANDS as f64));
+        }
+        self.seed = seed;
+        self.reset();
+        values.sort_by(|a,b| b.1.partial_cmp(&a.1).unwrap());
+        Ok(values)
+    }
+
+    ///the scores of the deck over random start hands (the whole hand kept): for every rep, the average of going first and second
+    fn deck_scores(&mut self) -> Vec<f64>
+    {
+        let mut orders = Vec::new();
+        for order in [PlayOrder::First, PlayOrder::Second]
+        {
+            self.reset();
+            self.set_play_order(order);
+            let do_print = false;
+            self.start_simulation(Vec::new(), Vec::new(), do_print).unwrap();
+            orders.push(std::mem::take(&mut self.rep_scores));
+        }
+        self.reset();
+        orders[0].iter().zip(orders[1].iter()).map(|(a,b)| (a + b) / 2.0).collect()
+    }
+
+    ///every swap of one card of the deck for a card with another power or a mana cost 1 away. the effects stay.
+    ///the new card can't have more than MAX_COPIES copies
+    fn card_swaps(&self) -> Vec<(Card, Card)>
+    {
+        let mut kinds = self.dealer.cards.iter().filter(|x| x.mana != -1).cloned().collect::<Vec<Card>>();
+        kinds.sort();
+        kinds.dedup();
+
+        let mut swaps = Vec::new();
+        for old in kinds.iter()
+        {
+            for mana in (old.mana-1).max(0)..=(old.mana+1).min(10)
+            {
+                for power in [CardPower::Weak, CardPower::Normal, CardPower::Strong]
+                {
+                    let new = Card{mana, card_power:power, effects:old.effects.clone()};
+                    if &new != old && self.dealer.cards.iter().filter(|x| x == &&new).count() < MAX_COPIES
+                    {
+                        swaps.push((old.clone(), new));
+                    }
+                }
+            }
+        }
+        swaps
+    }
+
+    ///hill climbing: every round try all card_swaps, print the ones raising the score of the deck, and do the best one.
+    ///a swap raises the score only if its 95% CI is above the CI of the deck, so the noise of many tries isn't taken as a gain.
+    ///stop when no swap helps. the deck size never changes
+    fn optimize_deck(&mut self, rounds:usize)
+    {
+        //every deck plays the same random games
+        let seed = self.seed;
+        if seed.is_none()
+        {
+            self.seed = Some(self.dealer.rng.u64(..));
+        }
+
+        let (mut score, mut std_error) = mean_and_std_error(&self.deck_scores());
+        println!("the score of the deck is {} (95% CI {:.3}~{:.3})", format!("{:.3}", score).yellow(), score - Z_95*std_error, score + Z_95*std_error);
+        for round in 1..=rounds
+        {
+            let mut better = Vec::new();
+            for (old, new) in self.card_swaps().into_iter()
+            {
+                let pos = self.dealer.cards.iter().position(|x| x == &old).unwrap();
+                self.dealer.cards[pos] = new.clone();
+                let (new_score, new_std_error) = mean_and_std_error(&self.deck_scores());
+                self.dealer.cards[pos] = old.clone();
+                if new_score - Z_95*new_std_error > score + Z_95*std_error
+                {
+                    better.push((old, new, new_score, new_std_error));
+                }
+            }
+            if better.is_empty()
+            {
+                println!("round {}: no swap raises the score outside the 95% CI", round);
+                break;
+            }
+
+            better.sort_by(|a,b| b.2.partial_cmp(&a.2).unwrap());
+            println!("round {}: {} swaps raise the score", round, better.len());
+            for (old, new, new_score, new_std_error) in better.iter()
+            {
+                println!("    {} -> {}: {} (95% CI {:.3}~{:.3})", old.to_card_string(), new.to_card_string(), format!("{:+.3}", new_score - score).green(),
+                            new_score - Z_95*new_std_error, new_score + Z_95*new_std_error);
+            }
+            let (old, new, new_score, new_std_error) = better.swap_remove(0);
+            let pos = self.dealer.cards.iter().position(|x| x == &old).unwrap();
+            self.dealer.cards[pos] = new.clone();
+            (score, std_error) = (new_score, new_std_error);
+            println!("swapping {} for {}, the score is {}", old.to_card_string(), new.to_card_string(), format!("{:.3}", score).yellow());
+        }
+        self.seed = seed;
+        self.dealer.sort_deck();
+    }
+
+    ///deal random start hands and play each with its best keep (see rank_patterns).
+    ///return the score of every hand and the average statistics of the best keeps
+    ///
+    ///every hand gets its own seed from seeder, so two decks given the same seeder play the same random streams
+    fn play_best_keeps(&mut self, order:PlayOrder, hands:usize, seeder:&fastrand::Rng) -> (Vec<f64>, SimStats)
+    {
+        let mut scores = Vec::new();
+        let mut stats = SimStats::new(self.maxturn);
+        for _ in 0..hands
+        {
+            self.reset();
+            self.set_play_order(order.clone());
+            self.dealer.rng = fastrand::Rng::with_seed(seeder.u64(..));
+            self.seed = Some(seeder.u64(..));
+
+            let mut deck = (0..self.dealer.cards.len()).filter(|&p| self.dealer.cards[p].mana != -1).collect::<Vec<usize>>();
+            let mut hand = (0..order.get_start_hand_size()).map(|_| deck.swap_remove(self.dealer.rng.usize(..deck.len()))).collect::<Vec<usize>>();
+            hand.sort();
+            let result = self.rank_patterns(&hand, false);
+            let the_best = result.last().unwrap();
+            scores.push(the_best.mean());
+            stats.merge(&the_best.stats);
+        }
+        stats.average(hands as i32);
+        self.reset();
+        (scores, stats)
+    }
+
+    ///load two decks and play the same random start hands with the best keeps. print the scores side by side
+    fn compare_decks(&self, files:[&str; 2], hands:usize) -> CommandResult
+    {
+        let seed = self.seed.unwrap_or_else(|| self.dealer.rng.u64(..));
+        let mut results = Vec::new();
+        for file in files.iter()
+        {
+            let mut sim = self.clone();
+            if let CommandResult::Err(e) = sim.dealer.load(file.to_string())
+            {
+                return CommandResult::Err(format!("{}: {}", file, e));
+            }
+            if sim.dealer.cards.iter().filter(|x| x.mana != -1).count() < PlayOrder::Second.get_start_hand_size() as usize
+            {
+                return CommandResult::Err(format!("{}: the deck is too small", file));
+            }
+            let mut result = Vec::new();
+            for order in [PlayOrder::First, PlayOrder::Second]
+            {
+                result.push(sim.play_best_keeps(order, hands, &fastrand::Rng::with_seed(seed)));
+            }
+            results.push(result);
+        }
+
+        println!("{} random start hands for each play order, seed {}", hands, seed);
+        println!("{:<16}{:>18}{:>18}", "", files[0], files[1]);
+        for (i, order) in [PlayOrder::First, PlayOrder::Second].iter().enumerate()
+        {
+            println!("going {:?}", order);
+            let summary = results.iter().map(|x| mean_and_std_error(&x[i].0)).collect::<Vec<(f64, f64)>>();
+            println!("{:<16}{:>18.3}{:>18.3}", "score", summary[0].0, summary[1].0);
+            let intervals = summary.iter().map(|(mean, se)| format!("{:.3}~{:.3}", mean - Z_95*se, mean + Z_95*se)).collect::<Vec<String>>();
+            println!("{:<16}{:>18}{:>18}", "95% CI", intervals[0], intervals[1]);
+            for turn in 0..self.maxturn as usize
+            {
+                println!("{:<16}{:>18.2}{:>18.2}", format!("mana used t{}", turn+1), results[0][i].1.mana_used[turn], results[1][i].1.mana_used[turn]);
+            }
+        }
+        CommandResult::Ok
+    }
+
+    ///set the seed of the random streams. None for a different result every run
+    fn set_seed(&mut self, seed:Option<u64>)
+    {
+        self.seed = seed;
+        self.dealer.rng = match seed
+        {
+            Some(seed)=>fastrand::Rng::with_seed(seed),
+            None=>fastrand::Rng::new(),
+        };
     }
 
     ///set play order. then add or delete coin according to play order
@@ -1100,63 +3062,83 @@ impl Simulator
         self.dealer.adjust_coin(order);
     }
 
-    fn sim_common_pattern(&mut self)
-    {
-        let kept_hand = vec![
-                                (vec![Card{mana:1, card_power:CardPower::Normal}],Vec::new(),"keeping 1"), //单留1费
-                                (vec![Card{mana:1, card_power:CardPower::Strong}],Vec::new(),"keeping 1(strong)"), //单留1费(强)
-                                (vec![Card{mana:2, card_power:CardPower::Normal}],Vec::new(),"keeping 2"), //单留2费
-                                (vec![Card{mana:3, card_power:CardPower::Normal}],Vec::new(),"keeping 3"), //单留3费
-                                (vec![Card{mana:3, card_power:CardPower::Strong}],Vec::new(),"keeping 3(strong)"), //单留3费(强)
-                                (vec![Card{mana:4, card_power:CardPower::Normal}],Vec::new(),"keeping 4"), //单留4费
-                                (vec![Card{mana:4, card_power:CardPower::Strong}],Vec::new(),"keeping 4(strong)"), //单留4费(强)
-                                (vec![Card{mana:2, card_power:CardPower::Normal},Card{mana:2, card_power:CardPower::Normal},Card{mana:3, card_power:CardPower::Normal}]
-                                    ,vec![Card{mana:2, card_power:CardPower::Normal},Card{mana:3, card_power:CardPower::Normal}],"having 2 3, keeping 2"), //有23,留2
-                                (vec![Card{mana:2, card_power:CardPower::Normal},Card{mana:3, card_power:CardPower::Normal},Card{mana:3, card_power:CardPower::Normal}]
-                                    ,vec![Card{mana:2, card_power:CardPower::Normal},Card{mana:3, card_power:CardPower::Normal}],"having 2 3, keeping 3"), //有23,留3
-                                (vec![Card{mana:1, card_power:CardPower::Normal}, Card{mana:3, card_power:CardPower::Normal}],vec![Card{mana:1, card_power:CardPower::Normal}],"having 1 and keeping 3"), //留1,3
-                                (vec![Card{mana:2, card_power:CardPower::Normal}, Card{mana:4, card_power:CardPower::Normal}],vec![Card{mana:2, card_power:CardPower::Normal}],"having 2 and keeping 4"), //留2,4
-                            ];
-        for pattern in kept_hand.into_iter()
+    ///print the value of every scenario: the score of keeping the whole hand minus the score of keeping the baseline
+    fn sim_common_pattern(&mut self, scenarios:Vec<Scenario>)
+    {
+        let play_order = self.play_order.clone();
+        for scenario in scenarios.into_iter()
         {
             let score1;
             let score2;
 
-            let hand1 = match self.dealer.cards_to_position(pattern.0, CardLocation::InDeck, None)
+            let start_hand_size = scenario.play_order.get_start_hand_size() as usize;
+            if scenario.hand.len() > start_hand_size
             {
-                Some(h)=>h,
-                None=>continue,
-            };
-            let hand2 = match self.dealer.cards_to_position(pattern.1, CardLocation::InDeck, None)
+                println!("skipping {}: the hand has more than {} cards", scenario.label.yellow(), start_hand_size);
+                continue;
+            }
+
+            self.reset();
+            self.set_play_order(scenario.play_order.clone());
+            let hand1 = match self.dealer.cards_to_position(scenario.hand, CardLocation::InDeck, None)
             {
                 Some(h)=>h,
-                None=>continue,
+                None=>
+                {
+                    println!("skipping {}: the hand is not in the deck", scenario.label.yellow());
+                    continue;
+                },
             };
 
+            //keep the same cards of hand1 for hand2, so the tossed ones are exactly the rest of hand1
+            let mut rest = hand1.clone();
+            let mut hand2 = Vec::new();
+            let mut missing = None;
+            for card in scenario.baseline.into_iter()
+            {
+                match rest.iter().position(|&p| self.dealer.cards[p] == card)
+                {
+                    Some(i)=>hand2.push(rest.swap_remove(i)),
+                    None=>{missing = Some(card); break;},
+                }
+            }
+            if let Some(card) = missing
+            {
+                println!("skipping {}: {:?} of the baseline is not in the hand", scenario.label.yellow(), card);
+                continue;
+            }
+            let tossed2 = rest;
+            if hand1.len() < start_hand_size
+            {
+                println!("{}: {} of the {} cards of the hand are drawn randomly", scenario.label.yellow(), start_hand_size - hand1.len(), start_hand_size);
+            }
+
             let do_print = false;
-            match self.start_simulation(hand1, do_print)
+            match self.start_simulation(hand1, Vec::new(), do_print)
             {
                 Some(s)=>score1=s,
-                None=>continue,
+                None=>{println!("skipping {}: failed to simulate the hand", scenario.label.yellow()); continue;},
             }
 
-            match self.start_simulation(hand2, do_print)
+            match self.start_simulation(hand2, tossed2, do_print)
             {
                 Some(s)=>score2=s,
-                None=>continue,
+                None=>{println!("skipping {}: failed to simulate the baseline", scenario.label.yellow()); continue;},
             }
 
             let score = score1 - score2;
             if score >= 0.000
             {
-                println!("the value of {} is {}", pattern.2, format!("{:.2}",score).to_string().green());
+                println!("the value of {} is {}", scenario.label, format!("{:.2}",score).to_string().green());
             }
             else
             {
-                println!("the value of {} is {}", pattern.2, format!("{:.2}",score).to_string().red());
+                println!("the value of {} is {}", scenario.label, format!("{:.2}",score).to_string().red());
             }
 
         }
+        self.reset();
+        self.set_play_order(play_order);
     }
 }
 
@@ -1221,9 +3203,95 @@ fn do_command(cmd:String, sim:&mut Simulator) -> CommandResult
             }
             println!();
         }
+        "stats"=>
+        {
+            let orders = match cmd.first().map(|x| x.as_str())
+            {
+                None=>vec![PlayOrder::First, PlayOrder::Second],
+                Some("first")=>vec![PlayOrder::First],
+                Some("second")=>vec![PlayOrder::Second],
+                _=>return CommandResult::Err("usage: stats [first|second]".to_string()),
+            };
+            for order in orders.into_iter()
+            {
+                sim.reset();
+                sim.set_play_order(order.clone());
+                let do_print = false;
+                let score = sim.start_simulation(Vec::new(), Vec::new(), do_print).unwrap();
+                println!("\ngoing {:?}, keeping the whole start hand. the score is {}", order, format!("{:.3}", score).yellow());
+                sim.stats.print();
+            }
+            sim.reset();
+        }
         "basic"=>
         {
-            sim.sim_common_pattern();
+            let filename = match cmd.first()
+            {
+                Some(w)=>w,
+                None=>"scenarios.txt",
+            };
+            match Scenario::load(filename)
+            {
+                Ok(scenarios)=>sim.sim_common_pattern(scenarios),
+                Err(e)=>return CommandResult::Err(e),
+            }
+        }
+        "guide"=>
+        {
+            return sim.mulligan_guide();
+        }
+        "compare"=>
+        {
+            let usage = "usage: compare <fileA> <fileB> [hands]".to_string();
+            let files = match (cmd.first(), cmd.get(1))
+            {
+                (Some(a), Some(b)) if !a.is_empty() && !b.is_empty()=>[a.as_str(), b.as_str()],
+                _=>return CommandResult::Err(usage),
+            };
+            let hands = match cmd.get(2)
+            {
+                Some(w)=>match w.parse()
+                {
+                    Ok(n)=>n,
+                    Err(_)=>return CommandResult::Err(usage),
+                },
+                None=>20,
+            };
+            return sim.compare_decks(files, hands);
+        }
+        "optimize"=>
+        {
+            let rounds = match cmd.first()
+            {
+                Some(w)=>match w.parse()
+                {
+                    Ok(n)=>n,
+                    Err(_)=>return CommandResult::Err("usage: optimize [rounds]".to_string()),
+                },
+                None=>3,
+            };
+            sim.optimize_deck(rounds);
+        }
+        "keepvalue"=>
+        {
+            for order in [PlayOrder::First, PlayOrder::Second]
+            {
+                let values = match sim.keep_values(order.clone())
+                {
+                    Ok(v)=>v,
+                    Err(e)=>return CommandResult::Err(e),
+                };
+                println!("\ngoing {:?}, the value of keeping each card:", order);
+                for (card, value) in values.into_iter()
+                {
+                    let value_str = format!("{:+.3}", value);
+                    println!("{:<8}{}", card.to_card_string(), match value >= 0.0
+                    {
+                        true=>value_str.green(),
+                        false=>value_str.red(),
+                    });
+                }
+            }
         }
         "clear"=>
         {
@@ -1261,17 +3329,71 @@ fn do_command(cmd:String, sim:&mut Simulator) -> CommandResult
             match cmd.get(0)
             {
                 Some(w)=>return sim.set_hero(w),
-                None=>return CommandResult::Err("failed to set hero.\n
-                                                dh for dh\n
-                                                wr for warrior\n
-                                                wl for warlock\n
-                                                ma for mage\n
-                                                pr for priest\n
-                                                dr for druid\n
-                                                sh for shaman\n
-                                                hu for hunter\n
-                                                pa for paladin\n
-                                                ro for rogue\n".to_string()),
+                None=>return CommandResult::Err(format!("failed to set hero.\n{}",
+                                                sim.heroes.iter().map(|x| format!("{} for {}", x.aliases.join(" or "), x.name)).collect::<Vec<String>>().join("\n"))),
+            }
+        }
+        "heropower"=>
+        {
+            let usage = "usage: heropower <cost> <value> [uses per turn] or heropower reset";
+            match cmd.first().map(|x| x.as_str())
+            {
+                None=>(),
+                Some("reset")=>
+                {
+                    if let Some(hero) = sim.heroes.iter().find(|x| x.name == sim.hero.name)
+                    {
+                        sim.hero.hero_power = hero.hero_power;
+                    }
+                },
+                Some(_)=>
+                {
+                    let (cost, value) = match (cmd[0].parse(), cmd.get(1).map(|x| x.parse()))
+                    {
+                        (Ok(c), Some(Ok(v)))=>(c, v),
+                        _=>return CommandResult::Err(usage.to_string()),
+                    };
+                    let uses_per_turn = match cmd.get(2).map(|x| x.parse())
+                    {
+                        None=>1,
+                        Some(Ok(n))=>n,
+                        Some(Err(_))=>return CommandResult::Err(usage.to_string()),
+                    };
+                    sim.hero.hero_power = HeroPower{cost, value, uses_per_turn};
+                },
+            }
+            println!("the hero power costs {}, is worth {} and can be used {} time(s) a turn",
+                        sim.hero.hero_power.cost, sim.hero.hero_power.value, sim.hero.hero_power.uses_per_turn);
+        }
+        "seed"=>
+        {
+            match cmd.first().map(|x| x.as_str())
+            {
+                None=>(),
+                Some("none")=>sim.set_seed(None),
+                Some(w)=>match w.parse()
+                {
+                    Ok(n)=>sim.set_seed(Some(n)),
+                    Err(_)=>return CommandResult::Err("usage: seed <number> or seed none".to_string()),
+                },
+            }
+            match sim.seed
+            {
+                Some(n)=>println!("the seed is {}", n),
+                None=>println!("no seed. every run is different"),
+            }
+        }
+        "savecode"=>
+        {
+            let db = match CardDb::load(CARD_DB_FILE)
+            {
+                Ok(db)=>db,
+                Err(e)=>return CommandResult::Err(e),
+            };
+            match sim.dealer.to_code(sim.hero.dbf_id, &db)
+            {
+                Ok(code)=>println!("the deck code is {}", code),
+                Err(e)=>return CommandResult::Err(e),
             }
         }
         "save"=>
@@ -1284,6 +3406,31 @@ fn do_command(cmd:String, sim:&mut Simulator) -> CommandResult
             sim.dealer.save(filename.to_string());
             println!("deck saved!");
         }
+        "loadcode"=>
+        {
+            let code = match cmd.first()
+            {
+                Some(w)=>w,
+                None=>return CommandResult::Err("usage: loadcode <deck code>".to_string()),
+            };
+            let db = match CardDb::load(CARD_DB_FILE)
+            {
+                Ok(db)=>db,
+                Err(e)=>return CommandResult::Err(e),
+            };
+            match sim.dealer.load_code(code, &db)
+            {
+                Ok(hero)=>
+                {
+                    if let Some(hero) = hero.and_then(|id| sim.heroes.iter().find(|x| x.dbf_id == id))
+                    {
+                        sim.hero = hero.clone();
+                    }
+                    println!("{} cards loaded, the hero is {}", sim.dealer.cards.len(), sim.hero.name);
+                },
+                Err(e)=>return CommandResult::Err(e),
+            }
+        }
         "load"=>
         {
             let filename = match cmd.get(0)
@@ -1295,7 +3442,7 @@ fn do_command(cmd:String, sim:&mut Simulator) -> CommandResult
         }
         "demo"=>
         {
-            sim.play_order = PlayOrder::flip_the_coin();
+            sim.play_order = PlayOrder::flip_the_coin(&sim.dealer.rng);
 
             let start_hand_size;
             match sim.play_order
@@ -1311,21 +3458,58 @@ fn do_command(cmd:String, sim:&mut Simulator) -> CommandResult
                 sim.draw_card();
             }
 
-            println!("the start hand is {:?}", sim.dealer.get_hand(&sim.dealer.card_location));
+            println!("the start hand is {:?}", sim.dealer.get_hand(&sim.dealer.card_location, &sim.dealer.turn_state));
             let hand = sim.hand.clone();
             let mull = sim.solve_mull(sim.hand.clone());
             sim.reset();
             
-            let keep = hand.into_iter().zip(mull.chars()).filter(|(_,mu)| mu==&'1').map(|(x,_) | x).collect::<Vec<usize>>();
-            sim.set_start_hand(&keep);
+            let mut hand = hand;
+            hand.sort();
+            let keep = hand.iter().zip(mull.chars()).filter(|(_,mu)| mu==&'1').map(|(&x,_) | x).collect::<Vec<usize>>();
+            let toss = hand.iter().zip(mull.chars()).filter(|(_,mu)| mu==&'0').map(|(&x,_) | x).collect::<Vec<usize>>();
+            if !sim.set_start_hand(&keep, &toss)
+            {
+                sim.reset();
+                return CommandResult::Err("failed to set the start hand".to_string());
+            }
             for i in 1..=10
             {
                 sim.draw_card();
                 let do_print = true;
-                sim.play_a_turn(None, i, sim.max_search_depth, do_print);
+                let depth = sim.horizon_depth(i, 10);
+                sim.play_a_turn(None, None, i, depth, do_print);
             }
             sim.reset();
         }
+        "bench"=>
+        {
+            let reps = match cmd.first()
+            {
+                Some(w)=>match w.parse()
+                {
+                    Ok(n)=>n,
+                    Err(_)=>return CommandResult::Err("usage: bench [reps]".to_string()),
+                },
+                None=>100,
+            };
+            let mut bench_sim = sim.clone();
+            bench_sim.cycle_reps = reps;
+            bench_sim.max_search_depth = 3;
+            bench_sim.set_seed(Some(0));
+            bench_sim.set_play_order(PlayOrder::First);
+            let mut times = Vec::new();
+            for use_play_cache in [false, true]
+            {
+                bench_sim.use_play_cache = use_play_cache;
+                bench_sim.play_cache = Arc::new(RwLock::new(HashMap::new()));
+                let start = Instant::now();
+                let score = bench_sim.start_simulation(Vec::new(), Vec::new(), false).unwrap();
+                let time = start.elapsed().as_secs_f64();
+                println!("play cache {}: {} reps in {:.3}s ({:.0} games/s), the score is {:.3}", use_play_cache, reps, time, reps as f64 / time, score);
+                times.push(time);
+            }
+            println!("speedup: {}", format!("{:.2}x", times[0]/times[1]).green());
+        }
         "test"=> //for test only
         {
         }
@@ -1341,6 +3525,13 @@ fn read_config() -> Result<Simulator, Box<dyn Error>>
     let mut maxturn:u8 = u8::default();
     let mut max_search_depth:u8 = u8::default();
     let mut play_card_bonus:i8 = i8::default();
+    let mut mulligan_rule = MulliganRule::Hearthstone;
+    let mut seed = None;
+    let mut threads = None;
+    let mut max_cycle_reps = 0;
+    let mut mode = SimMode::Full;
+    let mut lookahead = Lookahead::Sample;
+    let mut turn_weights = TurnWeights::Equal;
 
     let mut is_cycle_reps_set = false;
     let mut is_maxturn_set = false;
@@ -1354,8 +3545,14 @@ fn read_config() -> Result<Simulator, Box<dyn Error>>
 
     for config in contents.trim().split('\n')
     {
+        //a line starting with # is a comment, like the optional keys with their defaults
+        if config.trim().is_empty() || config.trim().starts_with('#')
+        {
+            continue;
+        }
         let config:Vec<&str> = config.trim().split(' ').collect();
-        if config.len()!=2
+        //turn_weights is the only key with more than one value
+        if config.len()!=2 && !(config[0] == "turn_weights" && config.len() > 2)
         {
             return Err(Box::new(std::io::Error::new(std::io::ErrorKind::InvalidInput,"")));
         }
@@ -1381,13 +3578,71 @@ fn read_config() -> Result<Simulator, Box<dyn Error>>
                 is_play_card_bonus_set = true;
                 play_card_bonus = config[1].parse()?;
             }
+            "seed"=>
+            {
+                seed = Some(config[1].parse()?);
+            }
+            "threads"=>
+            {
+                threads = Some(config[1].parse()?);
+            }
+            "max_cycle_reps"=>
+            {
+                max_cycle_reps = config[1].parse()?;
+            }
+            "turn_weights"=>
+            {
+                turn_weights = TurnWeights::List(config[1..].iter().map(|w| w.parse()).collect::<Result<Vec<f64>,_>>()?);
+            }
+            "turn_discount"=>
+            {
+                turn_weights = TurnWeights::Discount(config[1].parse()?);
+            }
+            "lookahead"=>
+            {
+                lookahead = match config[1]
+                {
+                    "sample"=>Lookahead::Sample,
+                    "exact"=>Lookahead::Exact,
+                    _=>return Err(Box::new(std::io::Error::new(std::io::ErrorKind::InvalidInput,""))),
+                };
+            }
+            "mode"=>
+            {
+                mode = match config[1]
+                {
+                    "full"=>SimMode::Full,
+                    "racing"=>SimMode::Racing,
+                    _=>return Err(Box::new(std::io::Error::new(std::io::ErrorKind::InvalidInput,""))),
+                };
+            }
+            "mulligan_rule"=>
+            {
+                mulligan_rule = match config[1]
+                {
+                    "hs" | "hearthstone"=>MulliganRule::Hearthstone,
+                    "redraw"=>MulliganRule::Redraw,
+                    _=>return Err(Box::new(std::io::Error::new(std::io::ErrorKind::InvalidInput,""))),
+                };
+            }
             _=>(),
         };
     }
 
-    if is_cycle_reps_set && is_maxturn_set && is_max_search_depth_set && is_play_card_bonus_set
+    if is_cycle_reps_set && cycle_reps >= 1 && is_maxturn_set && is_max_search_depth_set && is_play_card_bonus_set
     {
-        Ok(Simulator::new(cycle_reps, maxturn, max_search_depth, play_card_bonus))
+        let mut sim = Simulator::new(cycle_reps, maxturn, max_search_depth, play_card_bonus);
+        sim.mulligan_rule = mulligan_rule;
+        sim.set_seed(seed);
+        sim.max_cycle_reps = max_cycle_reps;
+        sim.mode = mode;
+        sim.lookahead = lookahead;
+        sim.turn_weights = turn_weights;
+        if let Some(n) = threads
+        {
+            sim.threads = n;
+        }
+        Ok(sim)
     }
     else
     {
@@ -1406,6 +3661,14 @@ fn main() {
             return;
         }
     };
+    if std::path::Path::new(HEROES_FILE).exists()
+    {
+        match Hero::load(HEROES_FILE)
+        {
+            Ok(heroes)=>sim.heroes = heroes,
+            Err(e)=>println!("{}, the built-in heroes are used", e),
+        }
+    }
 
     loop
     {
@@ -1430,12 +3693,12 @@ fn main() {
     {
         let sim = Simulator::new(100, 6, 2, 1);
         let hand = vec![
-                        Card{mana:2, card_power:CardPower::Normal},
-                        Card{mana:8, card_power:CardPower::Normal},
-                        Card{mana:8, card_power:CardPower::Normal},
-                        // Card{mana:3, card_power:CardPower::Normal},
-                        // Card{mana:2, card_power:CardPower::Normal},
-                        // Card{mana:1, card_power:CardPower::Normal},
+                        Card::new(2, CardPower::Normal),
+                        Card::new(8, CardPower::Normal),
+                        Card::new(8, CardPower::Normal),
+                        // Card::new(3, CardPower::Normal),
+                        // Card::new(2, CardPower::Normal),
+                        // Card::new(1, CardPower::Normal),
                     ];
         let all_plays = sim.get_all_play_patterns(hand, 9, 0);
         eprintln!("all_plays is {:?}", all_plays);
@@ -1447,12 +3710,12 @@ fn main() {
     {
         let sim = Simulator::new(100, 6, 2, 1);
         let hand = vec![
-                        Card{mana:4, card_power:CardPower::Normal},
-                        Card{mana:3, card_power:CardPower::Normal},
-                        Card{mana:3, card_power:CardPower::Normal},
-                        Card{mana:2, card_power:CardPower::Normal},
-                        Card{mana:5, card_power:CardPower::Normal},
-                        Card{mana:-1, card_power:CardPower::Normal},
+                        Card::new(4, CardPower::Normal),
+                        Card::new(3, CardPower::Normal),
+                        Card::new(3, CardPower::Normal),
+                        Card::new(2, CardPower::Normal),
+                        Card::new(5, CardPower::Normal),
+                        Card::new(-1, CardPower::Normal),
                     ];
         let all_plays = sim.get_all_play_patterns(hand, 4, 0);
         eprintln!("all_plays is {:?}", all_plays);
@@ -1464,8 +3727,8 @@ fn main() {
     {
         let sim = Simulator::new(100, 6, 2, 0);
         let hand = vec![
-                        Card{mana:5, card_power:CardPower::Normal},
-                        Card{mana:-1, card_power:CardPower::Normal},
+                        Card::new(5, CardPower::Normal),
+                        Card::new(-1, CardPower::Normal),
                     ];
         let all_plays = sim.get_all_play_patterns(hand, 4, 0);
         eprintln!("all_plays is {:?}", all_plays);
@@ -1478,10 +3741,10 @@ fn main() {
     {
         let mut sim = Simulator::new(100, 6, 2, 0);
         let hand = vec![
-                        Card{mana:1, card_power:CardPower::Normal},
-                        Card{mana:2, card_power:CardPower::Normal},
-                        Card{mana:2, card_power:CardPower::Normal},
-                        Card{mana:4, card_power:CardPower::Normal},
+                        Card::new(1, CardPower::Normal),
+                        Card::new(2, CardPower::Normal),
+                        Card::new(2, CardPower::Normal),
+                        Card::new(4, CardPower::Normal),
                     ];
         do_command("load".to_string(), &mut sim);
         sim.dealer.adjust_coin(PlayOrder::First);
@@ -1494,10 +3757,10 @@ fn main() {
     {
         let mut sim = Simulator::new(100, 6, 2, 0);
         let hand = vec![
-                        Card{mana:2, card_power:CardPower::Normal},
-                        Card{mana:2, card_power:CardPower::Strong},
-                        Card{mana:4, card_power:CardPower::Weak},
-                        Card{mana:4, card_power:CardPower::Strong},
+                        Card::new(2, CardPower::Normal),
+                        Card::new(2, CardPower::Strong),
+                        Card::new(4, CardPower::Weak),
+                        Card::new(4, CardPower::Strong),
                     ];
         do_command("load card_power_test".to_string(), &mut sim);
         sim.dealer.adjust_coin(PlayOrder::First);
@@ -1505,4 +3768,637 @@ fn main() {
         panic!("a");
     }
 
+    #[test]
+    fn card_effect_string()
+    {
+        let card = Card::create("n1d2").unwrap();
+        assert_eq!(card.mana, 1);
+        assert_eq!(card.effects, vec![CardEffect::Draw(2)]);
+        assert_eq!(Card::create("s10d1v").unwrap().to_card_string(), "s10d1v");
+        assert_eq!(Card::create_cards("2w3v").unwrap(), vec![Card{mana:3, card_power:CardPower::Weak, effects:vec![CardEffect::Discover]}; 2]);
+        assert_eq!(Card::create("n1x"), None);
+        //the power after the mana, like the old format
+        assert_eq!(Card::create("2n"), Some(Card::new(2, CardPower::Normal)));
+        assert_eq!(Card::create("3s"), Some(Card::new(3, CardPower::Strong)));
+        assert_eq!(Card::create("1wd1").unwrap().to_card_string(), "w1d1");
+        assert_eq!(Card::create("4"), Some(Card::new(4, CardPower::Normal)));
+    }
+
+    #[test]
+    fn play_draw_card()
+    {
+        let mut sim = Simulator::new(100, 6, 2, 0);
+        sim.dealer.insert_cards("1n1d2");
+        sim.dealer.insert_cards("5n3");
+        sim.reset();
+        assert!(sim.dealer.deck_to_hand(0));
+        sim.hand = vec![0];
+        sim.play_a_card(0, None);
+        assert_eq!(sim.hand.len(), 2);
+        assert_eq!(sim.dealer.get_hand(&sim.dealer.card_location, &sim.dealer.turn_state), Card::create_cards("2n3").unwrap());
+    }
+
+    #[test]
+    fn overdraw_and_fatigue()
+    {
+        let mut sim = Simulator::new(100, 6, 2, 0);
+        sim.dealer.insert_cards("12n1");
+        sim.reset();
+        for _ in 0..HAND_LIMIT
+        {
+            sim.draw_card();
+        }
+        assert_eq!(sim.hand.len(), HAND_LIMIT);
+        sim.draw_card();
+        sim.draw_card();
+        assert_eq!(sim.burned_cards, 2);
+        assert_eq!(sim.dealer.draw_card(None), DrawResult::Fatigue);
+        sim.draw_card();
+        sim.draw_card();
+        sim.draw_card();
+        assert_eq!(sim.fatigue_damage, 1+2+3);
+        assert_eq!(sim.hand.len(), HAND_LIMIT);
+    }
+
+    #[test]
+    fn run_out_of_cards_stats()
+    {
+        let mut sim = Simulator::new(4, 3, 1, 0);
+        sim.dealer.insert_cards("5n1");
+        sim.set_play_order(PlayOrder::First);
+        sim.start_simulation(Vec::new(), Vec::new(), false);
+        assert_eq!(sim.stats.hand_empty_turn, 3.0);
+        assert_eq!(sim.stats.hand_empty_rate, 1.0);
+        assert_eq!(sim.stats.deck_empty_turn, 2.0);
+        assert_eq!(sim.stats.fatigue_damage, 1.0);
+        assert_eq!(sim.stats.cards_in_hand, vec![3.0, 2.0, 0.0]);
+        assert_eq!(sim.stats.curve_out_rate, vec![1.0, 1.0, 0.0]);
+    }
+
+    #[test]
+    fn no_lookahead_after_last_turn()
+    {
+        let mut deep = Simulator::new(4, 2, 3, 1);
+        let mut shallow = Simulator::new(4, 2, 1, 1);
+        for sim in [&mut deep, &mut shallow]
+        {
+            sim.dealer.insert_cards("10n2");
+            sim.set_play_order(PlayOrder::First);
+        }
+        assert_eq!(deep.horizon_depth(2, 2), 1);
+        assert_eq!(deep.horizon_depth(1, 2), 2);
+        assert_eq!(deep.start_simulation(Vec::new(), Vec::new(), false), Some(9.0 + 11.0));
+
+        //the last turn alone must score the same as a depth-1 search
+        deep.maxturn = 1;
+        shallow.maxturn = 1;
+        assert_eq!(deep.start_simulation(Vec::new(), Vec::new(), false), shallow.start_simulation(Vec::new(), Vec::new(), false));
+    }
+
+    #[test]
+    fn slots_for_the_coin()
+    {
+        let mut sim = Simulator::new(10, 2, 1, 1);
+        assert!(matches!(sim.dealer.insert_cards(format!("{}n2", MAX_CARDS).as_str()), CommandResult::Err(_)));
+        assert_eq!(sim.dealer.cards.len(), MAX_CARDS - RESERVED_SLOTS);
+        sim.play_order = PlayOrder::Second;
+        sim.hero.starting_card = Card::create("n0m1");
+        assert!(sim.set_start_hand(&[], &[]));
+        assert_eq!(sim.dealer.get_hand(&sim.dealer.card_location, &sim.dealer.turn_state).len(), 6);
+        sim.reset();
+        assert_eq!(sim.dealer.cards.len(), MAX_CARDS - RESERVED_SLOTS);
+    }
+
+    #[test]
+    fn custom_evaluator()
+    {
+        struct CardCount;
+        impl Evaluator for CardCount
+        {
+            fn score_turn(&self, play:&[Card], _mana_left:i8, _hero:&Hero, _hero_power_uses:u8, _turn:u8) -> f64
+            {
+                play.len() as f64
+            }
+        }
+
+        let base = BaseEvaluator{play_card_bonus:1};
+        let mut hero = Hero::parse("tester | te | 2 0 | 1").unwrap();
+        assert_eq!(base.score_turn(&Card::create_cards("1s2").unwrap(), 1, &hero, 0, 3), 10.5);
+        hero.hero_power.value = 1.0;
+        assert_eq!(base.score_turn(&[], 0, &hero, 1, 2), 9.0);
+
+        let mut sim = Simulator::new(4, 2, 1, 1);
+        sim.dealer.insert_cards("10n2");
+        sim.set_play_order(PlayOrder::First);
+        sim.evaluator = Arc::new(CardCount);
+        assert_eq!(sim.start_simulation(Vec::new(), Vec::new(), false), Some(1.0));
+    }
+
+    #[test]
+    fn turn_weights()
+    {
+        assert_eq!(TurnWeights::List(vec![1.5, 1.2]).weight(1), 1.5);
+        assert_eq!(TurnWeights::List(vec![1.5, 1.2]).weight(5), 1.2);
+        assert_eq!(TurnWeights::Discount(0.5).weight(3), 0.25);
+
+        let mut sim = Simulator::new(4, 2, 2, 1);
+        sim.dealer.insert_cards("10n2");
+        sim.set_play_order(PlayOrder::First);
+        sim.turn_weights = TurnWeights::List(vec![2.0, 1.0]);
+        assert_eq!(sim.start_simulation(Vec::new(), Vec::new(), false), Some(2.0 * 9.0 + 11.0));
+    }
+
+    #[test]
+    fn start_hand_types()
+    {
+        let mut dealer = Dealer::new();
+        dealer.insert_cards("2n1");
+        dealer.insert_cards("3n2");
+        dealer.insert_card(Card::new(-1, CardPower::Normal));
+        let hands = dealer.hand_types(3);
+        assert_eq!(hands.len(), 3);
+        assert!((hands.iter().map(|(_,chance)| chance).sum::<f64>() - 1.0).abs() < 1e-9);
+        let (_, chance) = hands.iter().find(|(hand,_)| hand == &Card::create_cards("3n2").unwrap()).unwrap();
+        assert!((chance - 0.1).abs() < 1e-9);
+
+        //a deck smaller than the hand has no start hand
+        let mut dealer = Dealer::new();
+        dealer.insert_cards("2n1");
+        assert!(dealer.hand_types(3).is_empty());
+    }
+
+    #[test]
+    fn keep_cheap_cards()
+    {
+        let mut sim = Simulator::new(20, 3, 1, 1);
+        sim.dealer.insert_cards("10n1");
+        sim.dealer.insert_cards("10n8");
+        sim.set_seed(Some(7));
+        let values = sim.keep_values(PlayOrder::First).unwrap();
+        assert_eq!(values.iter().map(|(card,_)| card.mana).collect::<Vec<i8>>(), vec![1, 8]);
+        assert!(values[0].1 > 0.0 && values[1].1 < 0.0);
+        assert_eq!(sim.seed, Some(7));
+
+        let mut sim = Simulator::new(20, 3, 1, 1);
+        sim.dealer.insert_cards("2n1");
+        assert!(sim.keep_values(PlayOrder::First).is_err());
+    }
+
+    #[test]
+    fn parse_scenario()
+    {
+        let scenario = Scenario::parse("second | 2 s3 | | keeping 2 and 3").unwrap();
+        assert_eq!(scenario.play_order, PlayOrder::Second);
+        assert_eq!(scenario.hand, vec![Card::new(2, CardPower::Normal), Card::new(3, CardPower::Strong)]);
+        assert!(scenario.baseline.is_empty());
+        assert_eq!(scenario.label, "keeping 2 and 3");
+        assert_eq!(Scenario::parse("first | n2 | n2"), None);
+        assert_eq!(Scenario::parse("third | n2 | | x"), None);
+        assert!(Scenario::parse("first | n1 n2 n3 n4 | | x").is_some());
+
+        //a hand too big for the start hand is skipped, the other scenarios are still played
+        let mut sim = Simulator::new(10, 3, 1, 1);
+        sim.dealer.insert_cards("10n1");
+        let scenarios = ["first | n1 n1 n1 n1 | | too big", "first | n1 n1 | n1 | fine"].iter().map(|x| Scenario::parse(x).unwrap()).collect();
+        sim.sim_common_pattern(scenarios);
+        assert!(!Scenario::load("scenarios.txt").unwrap().is_empty());
+    }
+
+    #[test]
+    fn swaps_keep_two_copies()
+    {
+        let mut sim = Simulator::new(10, 3, 1, 1);
+        sim.dealer.insert_cards("2n2");
+        sim.dealer.insert_cards("1n10");
+        sim.set_play_order(PlayOrder::Second);
+        let swaps = sim.card_swaps();
+        assert!(swaps.iter().all(|(old,new)| old.mana != -1 && new != &Card::new(2, CardPower::Normal)));
+        assert!(swaps.iter().all(|(old,new)| (new.mana - old.mana).abs() <= 1 && new.mana <= 10));
+        assert_eq!(swaps.len(), 8 + 5);
+
+        sim.set_seed(Some(3));
+        sim.optimize_deck(1);
+        assert_eq!(sim.dealer.cards.len(), 3);
+        assert!(sim.dealer.cards.iter().all(|x| x.mana != -1));
+
+        //one card more or less in a big deck is lost in the noise: no swap is taken
+        let mut sim = Simulator::new(40, 6, 1, 1);
+        sim.dealer.insert_cards("15n2");
+        sim.dealer.insert_cards("15n3");
+        sim.set_seed(Some(3));
+        sim.optimize_deck(1);
+        assert_eq!(sim.dealer.cards.iter().filter(|x| x == &&Card::new(2, CardPower::Normal)).count(), 15);
+        assert_eq!(sim.dealer.cards.iter().filter(|x| x == &&Card::new(3, CardPower::Normal)).count(), 15);
+    }
+
+    #[test]
+    fn compare_same_hands()
+    {
+        let mut sim = Simulator::new(10, 3, 1, 1);
+        sim.dealer.insert_cards("10n2");
+        sim.dealer.insert_cards("10n3");
+        let mut other = sim.clone();
+        let (scores, stats) = sim.play_best_keeps(PlayOrder::Second, 5, &fastrand::Rng::with_seed(9));
+        assert_eq!(other.play_best_keeps(PlayOrder::Second, 5, &fastrand::Rng::with_seed(9)).0, scores);
+        assert_eq!(scores.len(), 5);
+        assert_eq!(stats.mana_used.len(), 3);
+        assert!(matches!(sim.compare_decks(["deck1", "no_such_deck"], 1), CommandResult::Err(_)));
+    }
+
+    #[test]
+    fn deck_code()
+    {
+        let code = "AAECAR8GxwPJBLsFmQfZB/gIDI0B2AGoArUDhwSSBe0G6wfbCe0JgQr+DAA=";
+        let (heroes, cards) = decode_deckstring(code).unwrap();
+        assert_eq!(heroes, vec![31]);
+        assert_eq!(cards.len(), 18);
+        assert_eq!(cards.iter().map(|(_,n)| n).sum::<u32>(), 30);
+        assert_eq!(decode_deckstring(&encode_deckstring(31, &cards)).unwrap(), (heroes, cards.clone()));
+
+        //every card of the deck costs 2 except the first one, which is a strong 1 drop
+        let entries = cards.iter().enumerate().map(|(i,(id,_))| match i
+        {
+            0=>format!("{{\"dbfId\":{},\"cost\":1,\"power\":\"s\"}}", id),
+            _=>format!("{{\"dbfId\":{},\"cost\":2,\"name\":\"x\"}}", id),
+        }).collect::<Vec<String>>();
+        let db = CardDb::from_json(&format!("[{}, {{\"name\":\"no cost\"}}]", entries.join(","))).unwrap();
+        let mut dealer = Dealer::new();
+        assert_eq!(dealer.load_code(code, &db), Ok(Some(31)));
+        assert_eq!(dealer.cards.len(), 30);
+        assert_eq!(dealer.cards[0], Card::new(1, CardPower::Strong));
+        assert_eq!(dealer.cards.iter().filter(|x| x.mana == 2).count(), 30 - cards[0].1 as usize);
+
+        let (_, exported) = decode_deckstring(&dealer.to_code(31, &db).unwrap()).unwrap();
+        assert!(exported.iter().all(|&(id,n)| n <= 2 && db.cards.contains_key(&id)));
+        let mut reloaded = Dealer::new();
+        reloaded.load_code(&dealer.to_code(31, &db).unwrap(), &db).unwrap();
+        assert_eq!(reloaded.cards, dealer.cards);
+        assert!(dealer.load_code("AAECAR8BAQAA", &db).is_err());
+    }
+
+    #[test]
+    fn cost_effects()
+    {
+        let innervate = Card::create("n0m1").unwrap();
+        assert_eq!(innervate.net_cost(), -1);
+        assert_eq!(Card::create("s4r1c2").unwrap().to_card_string(), "s4r1c2");
+
+        //innervate lets a 3 drop out on turn 2, like the coin
+        let sim = Simulator::new(10, 6, 1, 1);
+        let plays = sim.get_all_play_patterns(vec![Card::new(3, CardPower::Normal), innervate.clone()], 2, 0);
+        assert!(plays.iter().any(|x| x.len() == 2 && x.contains(&innervate)));
+
+        //a card giving mana is cast with its own cost first: a 2 mana card with 1 mana is never played, unless the coin pays for it
+        let big_innervate = Card::create("n2m3").unwrap();
+        let two = Card::new(2, CardPower::Normal);
+        let plays = sim.get_all_play_patterns(vec![big_innervate.clone(), two.clone()], 1, 0);
+        assert!(plays.iter().all(|x| !x.contains(&big_innervate) && !x.contains(&two)));
+        let coin = Card::create("n0m1").unwrap();
+        let plays = sim.get_all_play_patterns(vec![big_innervate.clone(), Card::new(3, CardPower::Normal), coin], 1, 0);
+        assert!(plays.iter().any(|x| x.len() == 3));
+
+        //after a discount card is played, the cards in hand cost 1 less
+        let mut sim = Simulator::new(10, 6, 1, 0);
+        sim.dealer.insert_cards("1n2r1");
+        sim.dealer.insert_cards("1n3");
+        sim.dealer.insert_cards("1n5c2");
+        let mut locations = sim.dealer.card_location;
+        let mut state = TurnState::default();
+        for pos in 0..3
+        {
+            locations.set(pos, CardLocation::InHand);
+        }
+        sim.dealer.end_turn(&[], 1, Some(&locations), Some(&mut state));
+        assert_eq!(sim.dealer.get_hand(&locations, &state).iter().map(|x| x.mana).collect::<Vec<i8>>(), vec![2, 3, 3]);
+        locations.set(0, CardLocation::NoWhere);
+        let played = vec![sim.dealer.cards[0].clone()];
+        sim.dealer.end_turn(&played, 1, Some(&locations), Some(&mut state));
+        assert_eq!(sim.dealer.get_hand(&locations, &state).iter().map(|x| x.mana).collect::<Vec<i8>>(), vec![2, 0]);
+        assert_eq!(sim.dealer.get_card_pos(Card::new(2, CardPower::Normal), &CardLocation::InHand, Some(&locations), Some(&state)), Some(1));
+
+        //a wasted mana is taken back by playing the discounted 3 drop on turn 2
+        assert_eq!(sim.play_a_turn(Some(&locations), Some(&state), 2, 1, false), 10.0);
+    }
+
+    #[test]
+    fn overload()
+    {
+        let card = Card::create("n3o1").unwrap();
+        assert_eq!(card.overload(), 1);
+        assert_eq!(card.to_card_string(), "n3o1");
+
+        //playing the overload card locks 1 mana of the next turn
+        let mut sim = Simulator::new(10, 6, 1, 0);
+        sim.dealer.insert_cards("1n3o1");
+        sim.dealer.insert_cards("1n3");
+        let mut locations = sim.dealer.card_location;
+        let mut state = TurnState::default();
+        locations.set(1, CardLocation::InHand);
+        sim.dealer.end_turn(std::slice::from_ref(&card), 2, Some(&locations), Some(&mut state));
+        assert_eq!(state.overload, 1);
+
+        //the 3 drop doesn't fit into the 2 mana left on turn 3
+        assert_eq!(sim.play_a_turn(Some(&locations), Some(&state), 3, 1, false), 8.0);
+        //the overload card isn't free: the locked crystal is a waste
+        assert_eq!(sim.evaluator.score_turn(&[card], 0, &sim.hero, 0, 3), 9.0);
+    }
+
+    #[test]
+    fn hero_power()
+    {
+        let mut sim = Simulator::new(10, 6, 1, 0);
+        sim.dealer.insert_cards("1n2");
+        sim.dealer.insert_cards("1n9");
+        let mut locations = sim.dealer.card_location;
+        let state = TurnState::default();
+        locations.set(0, CardLocation::InHand);
+        sim.set_hero("ro");
+
+        //a 2 drop is better than the hero power on turn 2
+        assert_eq!(sim.play_a_turn(Some(&locations), Some(&state), 2, 1, false), 10.0);
+        //3 mana left is enough for the hero power too
+        assert_eq!(sim.play_a_turn(Some(&locations), Some(&state), 5, 1, false), 8.2);
+
+        //the demon hunter can use it on turn 1
+        sim.set_hero("dh");
+        assert_eq!(sim.play_a_turn(Some(&locations), Some(&state), 1, 1, false), 9.5);
+
+        //a strong hero power is used instead of the 2 drop
+        sim.set_hero("ro");
+        sim.hero.hero_power = HeroPower{cost:2, value:5.0, uses_per_turn:1};
+        assert_eq!(sim.play_a_turn(Some(&locations), Some(&state), 2, 1, false), 13.0);
+
+        //an upgraded hero power with no cost, used twice
+        sim.hero.hero_power = HeroPower{cost:0, value:1.0, uses_per_turn:2};
+        assert_eq!(sim.play_a_turn(Some(&locations), Some(&state), 2, 1, false), 12.0);
+
+        //many uses a turn are still limited by the mana
+        assert_eq!(HeroPower{cost:1, value:2.0, uses_per_turn:200}.max_uses(3), 3);
+        sim.hero.hero_power = HeroPower{cost:1, value:2.0, uses_per_turn:200};
+        assert_eq!(sim.play_a_turn(Some(&locations), Some(&state), 3, 1, false), 13.0);
+    }
+
+    #[test]
+    fn custom_heroes()
+    {
+        let heroes = Hero::defaults();
+        assert_eq!(heroes.len(), 11);
+        assert!(heroes.iter().any(|x| x.is_called("demonhunter") && x.hero_power.cost == 1));
+        assert!(heroes.iter().any(|x| x.is_called("dk") && x.dbf_id == 78065));
+
+        let hero = Hero::parse("tester | te t | 0 1 2 | 1 | mana 1 card n0m1").unwrap();
+        assert_eq!(hero.aliases, vec!["te", "t"]);
+        assert_eq!(hero.hero_power, HeroPower{cost:0, value:1.0, uses_per_turn:2});
+        assert_eq!(hero.starting_mana, 1);
+        assert_eq!(hero.starting_card, Card::create("n0m1"));
+        assert_eq!(Hero::parse("tester | te | 2 | 1"), None);
+        assert_eq!(Hero::parse("tester | te | 2 1 | 1 | card"), None);
+
+        //the starting card is put into hand after the mulligan and deleted when the deck is reset
+        let mut sim = Simulator::new(2, 2, 1, 1);
+        sim.dealer.insert_cards("10n2");
+        sim.heroes.push(hero);
+        sim.set_hero("t");
+        sim.set_start_hand(&Vec::new(), &[]);
+        assert_eq!(sim.dealer.get_hand(&sim.dealer.card_location, &sim.dealer.turn_state).len(), 4);
+        assert!(sim.dealer.get_hand(&sim.dealer.card_location, &sim.dealer.turn_state).contains(&Card::create("n0m1").unwrap()));
+        sim.reset();
+        assert_eq!(sim.dealer.cards.len(), 10);
+    }
+
+    #[test]
+    fn mana_crystals()
+    {
+        let wild_growth = Card::create("n2e1").unwrap();
+        assert_eq!(wild_growth.to_card_string(), "n2e1");
+        assert_eq!(Card::create("n3f2").unwrap().net_cost(), 1);
+
+        //a full crystal makes nourish cost 3, but it can't be cast before there are 5 mana
+        let nourish = Card::create("n5f2").unwrap();
+        let sim = Simulator::new(10, 6, 1, 0);
+        for mana in 3..=4
+        {
+            assert!(sim.get_all_play_patterns(vec![nourish.clone()], mana, 0).iter().all(|x| !x.contains(&nourish)));
+        }
+        assert_eq!(sim.get_all_play_patterns(vec![nourish.clone()], 5, 0), vec![vec![nourish.clone()]]);
+        let mut sim = Simulator::new(10, 6, 1, 0);
+        sim.dealer.insert_cards("1n5f2");
+        let mut locations = sim.dealer.card_location;
+        let state = TurnState::default();
+        locations.set(0, CardLocation::InHand);
+        //nothing is played, the 3 mana are wasted
+        assert_eq!(sim.play_a_turn(Some(&locations), Some(&state), 3, 1, false), 7.0);
+
+        let mut sim = Simulator::new(10, 6, 1, 0);
+        sim.dealer.insert_cards("1n2e1");
+        sim.dealer.insert_cards("1n4");
+        let mut locations = sim.dealer.card_location;
+        let mut state = TurnState::default();
+        locations.set(1, CardLocation::InHand);
+
+        //the ramp on turn 2 gives 4 mana on turn 3
+        sim.dealer.end_turn(std::slice::from_ref(&wild_growth), 2, Some(&locations), Some(&mut state));
+        assert_eq!(state.crystals(3), 4);
+        assert_eq!(sim.play_a_turn(Some(&locations), Some(&state), 3, 1, false), 10.0);
+
+        //destroyed crystals come back one a turn, and there are never more than 10
+        sim.dealer.end_turn(&Card::create_cards("1n0x2").unwrap(), 3, Some(&locations), Some(&mut state));
+        assert_eq!(state.crystals(4), 3);
+        assert_eq!(state.crystals(20), 10);
+        sim.dealer.end_turn(&Card::create_cards("1n0e3").unwrap(), 9, Some(&locations), Some(&mut state));
+        assert_eq!(state.crystals(10), 10);
+
+        //a long game doesn't overflow the mana
+        let mut sim = Simulator::new(2, 150, 1, 1);
+        sim.dealer.insert_cards("30n5");
+        assert!(sim.start_simulation(Vec::new(), Vec::new(), false).is_some());
+    }
+
+    #[test]
+    fn same_seed_same_result()
+    {
+        let mut sim = Simulator::new(40, 6, 2, 1);
+        do_command("load deck1".to_string(), &mut sim);
+        sim.set_play_order(PlayOrder::Second);
+        sim.set_seed(Some(42));
+        let hand = sim.dealer.cards_to_position(Card::create_cards("1n2").unwrap(), CardLocation::InDeck, None).unwrap();
+        let score = sim.start_simulation(hand.clone(), Vec::new(), false).unwrap();
+        for _ in 0..3
+        {
+            assert_eq!(score.to_bits(), sim.start_simulation(hand.clone(), Vec::new(), false).unwrap().to_bits());
+        }
+    }
+
+    #[test]
+    fn reps_spread_over_threads()
+    {
+        let mut sim = Simulator::new(10, 6, 2, 1);
+        do_command("load deck1".to_string(), &mut sim);
+        sim.set_play_order(PlayOrder::First);
+        sim.set_seed(Some(1));
+        let mut scores = Vec::new();
+        for threads in [1, 3, 8, 64]
+        {
+            sim.threads = threads;
+            scores.push(sim.start_simulation(Vec::new(), Vec::new(), false).unwrap().to_bits());
+        }
+        assert!(scores.iter().all(|x| x == &scores[0]));
+    }
+
+    #[test]
+    fn confidence_interval()
+    {
+        let (mean, std_error) = mean_and_std_error(&[1.0, 2.0, 3.0, 4.0]);
+        assert_eq!(mean, 2.5);
+        assert!((std_error - (5.0f64/3.0/4.0).sqrt()).abs() < 1e-12);
+
+        let mut a = KeepPattern{pattern:"1".to_string(), kept:Vec::new(), tossed:Vec::new(), cards:Vec::new(), scores:vec![10.0, 11.0, 12.0], stats:SimStats::default()};
+        let mut b = a.clone();
+        assert!(a.is_tied_with(&b));
+        b.scores = vec![20.0, 21.0, 22.0];
+        assert!(!a.is_tied_with(&b));
+        a.scores = vec![10.0, 30.0];
+        assert!(a.is_tied_with(&b));
+    }
+
+    #[test]
+    fn racing_drops_bad_patterns()
+    {
+        let mut sim = Simulator::new(400, 6, 1, 1);
+        sim.dealer.insert_cards("15n2");
+        sim.dealer.insert_cards("15n9");
+        sim.set_play_order(PlayOrder::First);
+        sim.set_seed(Some(5));
+        let hand = sim.dealer.cards_to_position(Card::create_cards("3n2").unwrap(), CardLocation::InDeck, None).unwrap();
+        let mut patterns = sim.keep_patterns(&hand);
+        assert_eq!(patterns.len(), 4);
+        patterns = sim.race_patterns(patterns);
+        patterns.sort_by(|a,b| a.mean().partial_cmp(&b.mean()).unwrap());
+        assert_eq!(patterns.last().unwrap().cards, Card::create_cards("3n2").unwrap());
+        //the reps of the dropped patterns go to the best one, and the total stays in the budget
+        assert!(patterns.last().unwrap().scores.len() > 400);
+        assert!(patterns[0].scores.len() < 400);
+        assert!(patterns.iter().map(|x| x.scores.len()).sum::<usize>() <= 4 * 400);
+    }
+
+    #[test]
+    fn no_reps_no_tie_break()
+    {
+        //with no reps the patterns have no scores, and the tie break must end
+        let mut sim = Simulator::new(0, 6, 1, 1);
+        sim.max_cycle_reps = 100;
+        sim.dealer.insert_cards("30n2");
+        sim.set_play_order(PlayOrder::First);
+        let hand = sim.dealer.cards_to_position(Card::create_cards("3n2").unwrap(), CardLocation::InDeck, None).unwrap();
+        assert!(sim.rank_patterns(&hand, false).iter().all(|x| x.scores.is_empty()));
+    }
+
+    #[test]
+    fn exact_lookahead()
+    {
+        let mut sim = Simulator::new(100, 6, 3, 1);
+        sim.dealer.insert_cards("2n1");
+        sim.dealer.insert_cards("6n3");
+        sim.dealer.insert_cards("2n2");
+        let outcomes = sim.dealer.draw_outcomes(sim.dealer.card_location);
+        assert_eq!(outcomes.len(), 3);
+        assert_eq!(outcomes.iter().map(|x| x.0).sum::<f64>(), 1.0);
+        assert_eq!(outcomes[1].0, 0.6);
+
+        //with 2 mana and only 3-drops left, turn 2 wastes the mana, turn 3 plays one
+        let mut locations = sim.dealer.card_location;
+        let state = TurnState::default();
+        for pos in [0, 1, 8, 9]
+        {
+            locations.set(pos, CardLocation::NoWhere);
+        }
+        locations.set(2, CardLocation::InHand);
+        sim.lookahead = Lookahead::Exact;
+        let exact = sim.play_a_turn(Some(&locations), Some(&state), 2, 2, false);
+        assert_eq!(exact, 8.0 + 11.0);
+        assert!(!sim.memo.is_empty());
+        sim.lookahead = Lookahead::Sample;
+        assert_eq!(sim.play_a_turn(Some(&locations), Some(&state), 2, 2, false), exact);
+    }
+
+    #[test]
+    fn play_cache_same_plays()
+    {
+        let sim = Simulator::new(100, 6, 2, 1);
+        let hand = vec![Card::new(3, CardPower::Normal), Card::new(-1, CardPower::Normal), Card::new(2, CardPower::Normal), Card::new(1, CardPower::Strong)];
+        let plays = sim.get_all_play_patterns(hand.clone(), 3, 0);
+        assert_eq!(sim.play_cache.read().unwrap().len(), 1);
+        let mut reversed = hand.clone();
+        reversed.reverse();
+        assert_eq!(sim.get_all_play_patterns(reversed, 3, 0), plays);
+        assert_eq!(sim.play_cache.read().unwrap().len(), 1);
+
+        let mut no_cache = sim.clone();
+        no_cache.use_play_cache = false;
+        no_cache.play_cache = Arc::new(RwLock::new(HashMap::new()));
+        assert_eq!(no_cache.get_all_play_patterns(hand, 3, 0), plays);
+        assert!(no_cache.play_cache.read().unwrap().is_empty());
+    }
+
+    #[test]
+    fn mulligan_keeps_tossed_cards_out()
+    {
+        let mut sim = Simulator::new(100, 6, 2, 0);
+        sim.dealer.insert_cards("3n1");
+        sim.dealer.insert_cards("3n5");
+        sim.set_play_order(PlayOrder::First);
+        let tossed = vec![0, 1, 2];
+        for _ in 0..100
+        {
+            sim.reset();
+            assert!(sim.set_start_hand(&Vec::new(), &tossed));
+            assert_eq!(sim.dealer.get_hand(&sim.dealer.card_location, &sim.dealer.turn_state), Card::create_cards("3n5").unwrap());
+            assert!(tossed.iter().all(|&p| sim.dealer.card_location.get(p) == CardLocation::InDeck));
+        }
+
+        //a card that can't be tossed leaves nothing aside, and the simulation fails
+        sim.reset();
+        assert!(!sim.dealer.set_aside(&[0, 1, 99]));
+        assert_eq!(sim.dealer.card_location.aside, 0);
+        assert_eq!(sim.start_simulation(Vec::new(), vec![0, 99], false), None);
+    }
+
+    #[test]
+    fn card_locations_bits()
+    {
+        let mut locations = CardLocations::new_deck(5);
+        locations.set(1, CardLocation::InHand);
+        locations.set(3, CardLocation::NoWhere);
+        assert_eq!(locations.count(CardLocation::InDeck), 3);
+        assert_eq!(locations.positions(CardLocation::InDeck).collect::<Vec<usize>>(), vec![0, 2, 4]);
+        assert_eq!(locations.nth(CardLocation::InDeck, 2), 4);
+        locations.remove(1);
+        assert_eq!(locations.positions(CardLocation::InDeck).collect::<Vec<usize>>(), vec![0, 1, 3]);
+        assert_eq!(locations.get(2), CardLocation::NoWhere);
+        locations.swap_remove(0, 3);
+        assert_eq!(locations.positions(CardLocation::InDeck).collect::<Vec<usize>>(), vec![0, 1]);
+        assert_eq!(CardLocations::new_deck(MAX_CARDS).count(CardLocation::InDeck), MAX_CARDS);
+
+        //every card in the deck is drawn about as often
+        let mut dealer = Dealer::new();
+        dealer.rng = fastrand::Rng::with_seed(1);
+        for mana in 1..5
+        {
+            dealer.insert_card(Card::new(mana, CardPower::Normal));
+        }
+        let mut counts = [0; 4];
+        for _ in 0..4000
+        {
+            let mut locations = dealer.card_location;
+            match dealer.draw_card(Some(&mut locations))
+            {
+                DrawResult::InHand(p)=>counts[p] += 1,
+                _=>panic!("should draw into hand"),
+            }
+        }
+        assert!(counts.iter().all(|&c| (800..1200).contains(&c)));
+        assert_eq!(dealer.insert_cards("58n2"), CommandResult::Ok);
+        assert!(dealer.insert_card(Card::new(2, CardPower::Normal)) != CommandResult::Ok);
+    }
+
 }